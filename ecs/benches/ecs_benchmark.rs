@@ -4,8 +4,11 @@ extern crate ecs;
 extern crate crossbeam;
 use ecs::entity::*;
 use ecs::component::*;
+use ecs::component::inline::InlineComponentStorage;
 use criterion::Criterion;
 use ecs::ECS;
+use ecs::archetype::Layout;
+use ecs::serialize::TypeRegistry;
 use crossbeam::thread;
 
 const NUM_POSITION_ONLY: usize = 9000;
@@ -39,7 +42,37 @@ fn setup_pos_vel() -> ECS {
     ecs.register_new_component::<StubVelocity>().expect("unable to register new component");
     ecs.register_new_component::<StubPosition>().expect("unable to register new component");
     for ent in entities {
-        if ent.0 % NUM_POSITION_AND_VELOCITY == 0 {
+        if ent.index() % NUM_POSITION_AND_VELOCITY == 0 {
+            ecs.add_component(ent, StubVelocity { dx: 32.0, dy: 32.0 }).expect("not registered");
+        }
+        ecs.add_component(ent, StubPosition { x: 1.0, y: 10.0 }).expect("not registered");
+    }
+    ecs
+}
+
+fn build_archetype(number: usize) -> (Vec<EntityIndex>, ECS) {
+    let mut entities = vec![];
+    let mut ecs = ECS::with_layout(Layout::Archetype);
+    for _ in 0..number {
+        entities.push(ecs.allocate_new_entity());
+    }
+    (entities, ecs)
+}
+
+fn setup_parallel_archetype() -> ECS{
+    let (entities, mut ecs) = build_archetype(STANDARD);
+    for ent in entities {
+        ecs.add_component(ent, R { x: 32.0 }).expect("not registered");
+        ecs.add_component(ent, W1 { x: 0.0 }).expect("not registered");
+        ecs.add_component(ent, W2 { x: 0.0 }).expect("not registered");
+    }
+    ecs
+}
+
+fn setup_pos_vel_archetype() -> ECS {
+    let (entities, mut ecs) = build_archetype(NUM_POSITION_ONLY);
+    for ent in entities {
+        if ent.index() % NUM_POSITION_AND_VELOCITY == 0 {
             ecs.add_component(ent, StubVelocity { dx: 32.0, dy: 32.0 }).expect("not registered");
         }
         ecs.add_component(ent, StubPosition { x: 1.0, y: 10.0 }).expect("not registered");
@@ -56,7 +89,7 @@ fn ecs_deallocate_empty_entity(c: &mut Criterion){
 }
 
 fn ecs_deallocate_entity_with_component(c: &mut Criterion){
-    c.bench_function("ecs deallocate entities with component", move |b| b.iter_with_large_setup(|| setup_pos_vel(), |mut ecs|{ecs.deallocate_entity((50, 0)).expect("unable to deallocate entity");}));
+    c.bench_function("ecs deallocate entities with component", move |b| b.iter_with_large_setup(|| setup_pos_vel(), |mut ecs|{ecs.deallocate_entity(ecs.entity_list.entity(50).unwrap()).expect("unable to deallocate entity");}));
 }
 
 fn ecs_register_component(c: &mut Criterion){
@@ -72,7 +105,7 @@ fn ecs_add_new_component(c: &mut Criterion){
 }
 
 fn ecs_remove_component(c: &mut Criterion){
-    c.bench_function("ecs remove component", move |b| b.iter_with_large_setup(|| setup_pos_vel(), |mut ecs| {ecs.remove_component::<StubPosition>((66, 0)).expect("unable to remove component");}));
+    c.bench_function("ecs remove component", move |b| b.iter_with_large_setup(|| setup_pos_vel(), |mut ecs| {ecs.remove_component::<StubPosition>(ecs.entity_list.entity(66).unwrap()).expect("unable to remove component");}));
 }
 
 fn ecs_fetch_component(c: &mut Criterion){
@@ -140,7 +173,138 @@ fn ecs_parallel_systems(c: &mut Criterion){
 
 
 
-fn system_w1(read_r: ComponentIterator<R>, write_w1: ComponentIteratorMut<W1>) {
+fn setup_pos_vel_inline() -> ECS {
+    let (entities, mut ecs) = build(NUM_POSITION_ONLY);
+    ecs.register_new_component::<InlineVelocity>().expect("unable to register new component");
+    ecs.register_new_component::<InlinePosition>().expect("unable to register new component");
+    for ent in entities {
+        if ent.index() % NUM_POSITION_AND_VELOCITY == 0 {
+            ecs.add_component(ent, InlineVelocity { dx: 32.0, dy: 32.0 }).expect("not registered");
+        }
+        ecs.add_component(ent, InlinePosition { x: 1.0, y: 10.0 }).expect("not registered");
+    }
+    ecs
+}
+
+fn inline_pos_vel_update(c: &mut Criterion){
+    let ecs = setup_pos_vel_inline();
+    c.bench_function("inline_pos_vel_update", move |b|b.iter(||{
+        let h1 = ecs.get_component_read_handle::<InlineVelocity>();
+        let mut h2 = ecs.get_component_write_handle::<InlinePosition>();
+        system_movement_inline(&h1, &mut h2);
+    }));
+}
+
+fn inline_chunked_pos_vel_update(c: &mut Criterion){
+    let ecs = setup_pos_vel_inline();
+    c.bench_function("inline_chunked_pos_vel_update", move |b|b.iter(||{
+        let h1 = ecs.get_component_read_handle::<InlineVelocity>();
+        let mut h2 = ecs.get_component_write_handle::<InlinePosition>();
+        system_movement_chunked(&h1, &mut h2);
+    }));
+}
+
+//spawns and despawns in waves, leaving one entity in ten alive and the storages full of empty slots
+fn setup_churn() -> ECS {
+    let (_, mut ecs) = build(0);
+    ecs.register_new_component::<StubVelocity>().expect("unable to register new component");
+    ecs.register_new_component::<StubPosition>().expect("unable to register new component");
+    for _ in 0..10 {
+        let mut wave = vec![];
+        for _ in 0..STANDARD {
+            let ent = ecs.allocate_new_entity();
+            ecs.add_component(ent, StubPosition { x: 1.0, y: 10.0 }).expect("not registered");
+            ecs.add_component(ent, StubVelocity { dx: 32.0, dy: 32.0 }).expect("not registered");
+            wave.push(ent);
+        }
+        for ent in wave.into_iter().filter(|e| e.index() % 10 != 0) {
+            ecs.deallocate_entity(ent).expect("unable to deallocate entity");
+        }
+    }
+    ecs
+}
+
+fn ecs_churn_maintain(c: &mut Criterion){
+    let mut ecs = setup_churn();
+    let before = ecs.heap_size();
+    ecs.maintain();
+    println!("churn: {} bytes before maintain, {} bytes after", before, ecs.heap_size());
+    c.bench_function("ecs churn maintain", move |b| b.iter_with_large_setup(|| setup_churn(), |mut ecs| ecs.maintain()));
+}
+
+fn ecs_churn_compact(c: &mut Criterion){
+    let mut ecs = setup_churn();
+    let before = ecs.heap_size();
+    ecs.compact(&TypeRegistry::new());
+    println!("churn: {} bytes before compact, {} bytes after", before, ecs.heap_size());
+    c.bench_function("ecs churn compact", move |b| b.iter_with_large_setup(|| setup_churn(), |mut ecs| {ecs.compact(&TypeRegistry::new());}));
+}
+
+fn ecs_churn_update_compacted(c: &mut Criterion){
+    let mut ecs = setup_churn();
+    ecs.compact(&TypeRegistry::new());
+    c.bench_function("ecs churn pos_vel_update after compact", move |b|b.iter(||{
+        let h1 = ecs.get_component_read_handle::<StubVelocity>();
+        let mut h2 = ecs.get_component_write_handle::<StubPosition>();
+        system_movement(h1.get_iterator(), h2.get_mut_iter());
+    }));
+}
+
+fn ecs_churn_update(c: &mut Criterion){
+    let ecs = setup_churn();
+    c.bench_function("ecs churn pos_vel_update", move |b|b.iter(||{
+        let h1 = ecs.get_component_read_handle::<StubVelocity>();
+        let mut h2 = ecs.get_component_write_handle::<StubPosition>();
+        system_movement(h1.get_iterator(), h2.get_mut_iter());
+    }));
+}
+
+fn archetype_allocate_new_entities_pos_vel(c: &mut Criterion){
+    c.bench_function("archetype add new entities", move |b| b.iter(|| {setup_pos_vel_archetype();}));
+}
+
+fn archetype_deallocate_entity_with_component(c: &mut Criterion){
+    c.bench_function("archetype deallocate entities with component", move |b| b.iter_with_large_setup(|| setup_pos_vel_archetype(), |mut ecs|{ecs.deallocate_entity(ecs.entity_list.entity(50).unwrap()).expect("unable to deallocate entity");}));
+}
+
+fn archetype_add_new_component(c: &mut Criterion){
+    c.bench_function("archetype add new component", move |b| b.iter_with_large_setup(|| setup_pos_vel_archetype(),
+                                                                               |mut ecs| {ecs.add_component(ecs.entity_list.entity(20).unwrap(), StubVelocity{dx: 0.0, dy: 0.0}).expect("not registered");}));
+}
+
+fn archetype_remove_component(c: &mut Criterion){
+    c.bench_function("archetype remove component", move |b| b.iter_with_large_setup(|| setup_pos_vel_archetype(), |mut ecs| {ecs.remove_component::<StubPosition>(ecs.entity_list.entity(66).unwrap()).expect("unable to remove component");}));
+}
+
+fn archetype_fetch_component(c: &mut Criterion){
+    let ecs = setup_pos_vel_archetype();
+    c.bench_function("archetype fetch component", move |b| b.iter(||{
+        let mut result = Vec::with_capacity(NUM_POSITION_ONLY);
+        ecs.archetypes.as_ref().expect("archetype layout").for_each(|_, p: &StubPosition| result.push(p));
+    }));
+}
+
+fn archetype_pos_vel_update(c: &mut Criterion){
+    let mut ecs = setup_pos_vel_archetype();
+    c.bench_function("archetype_pos_vel_update", move |b|b.iter(||{
+        ecs.archetypes.as_mut().expect("archetype layout").for_each_pair(|v: &StubVelocity, p: &mut StubPosition| {
+            p.x += v.dx;
+            p.y += v.dy;
+        });
+    }));
+}
+
+fn archetype_sequential_systems(c: &mut Criterion) {
+    let mut ecs = setup_parallel_archetype();
+    c.bench_function("archetype sequential systems", move |b| b.iter( ||{
+        let archetypes = ecs.archetypes.as_mut().expect("archetype layout");
+        archetypes.for_each_pair(|r: &R, w1: &mut W1| w1.x = r.x);
+        archetypes.for_each_pair(|r: &R, w2: &mut W2| w2.x = r.x);
+    }
+    ));
+}
+
+fn system_w1(read_r: ComponentIterator<R>, write_w1: Tracked<ComponentIteratorMut<W1>>) {
     let joint = read_r.join(write_w1);
     let iterator = joint.into_iterator_wrapper();
     for (r, w1) in iterator {
@@ -148,7 +312,7 @@ fn system_w1(read_r: ComponentIterator<R>, write_w1: ComponentIteratorMut<W1>) {
     }
 }
 
-fn system_w2(read_r: ComponentIterator<R>, write_w2: ComponentIteratorMut<W2>){
+fn system_w2(read_r: ComponentIterator<R>, write_w2: Tracked<ComponentIteratorMut<W2>>){
     let joint = read_r.join(write_w2);
     let iterator = joint.into_iterator_wrapper();
     for (r, w2) in iterator {
@@ -156,7 +320,7 @@ fn system_w2(read_r: ComponentIterator<R>, write_w2: ComponentIteratorMut<W2>){
     }
 }
 
-fn system_movement(read: ComponentIterator<StubVelocity>, writer: ComponentIteratorMut<StubPosition>) {
+fn system_movement(read: ComponentIterator<StubVelocity>, writer: Tracked<ComponentIteratorMut<StubPosition>>) {
     let joint = read.join(writer);
     let iter = joint.into_iterator_wrapper();
     for (v, p) in iter {
@@ -165,6 +329,50 @@ fn system_movement(read: ComponentIterator<StubVelocity>, writer: ComponentItera
     }
 }
 
+fn system_movement_inline(read: &InlineComponentStorage<InlineVelocity>, writer: &mut ComponentWriteHandle<InlineComponentStorage<InlinePosition>>) {
+    let joint = read.get_iter().join(writer.get_mut_iter());
+    for (v, p) in joint.into_iterator_wrapper() {
+        p.x += v.dx;
+        p.y += v.dy;
+    }
+}
+
+//only the overlap of each pair of runs is updated, as a plain loop over two slices
+fn system_movement_chunked(read: &InlineComponentStorage<InlineVelocity>, writer: &mut ComponentWriteHandle<InlineComponentStorage<InlinePosition>>) {
+    for (start, positions) in writer.chunks_mut() {
+        for (vstart, velocities) in read.chunks() {
+            let from = start.max(vstart);
+            let to = (start + positions.len()).min(vstart + velocities.len());
+            if from < to {
+                for (p, v) in positions[from - start..to - start].iter_mut().zip(velocities[from - vstart..to - vstart].iter()) {
+                    p.x += v.dx;
+                    p.y += v.dy;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct InlinePosition{
+    pub x: f32,
+    pub y: f32
+}
+
+impl Component for InlinePosition{
+    type ComponentStorage = InlineComponentStorage<Self>;
+}
+
+#[derive(Clone, Default)]
+struct InlineVelocity{
+    pub dx: f32,
+    pub dy: f32
+}
+
+impl Component for InlineVelocity{
+    type ComponentStorage = InlineComponentStorage<Self>;
+}
+
 #[derive(Clone)]
 struct R{
     pub x: f32
@@ -172,10 +380,6 @@ struct R{
 
 impl Component for R{
     type ComponentStorage = DenseComponentStorage<Self>;
-
-    fn update(&mut self) {
-        unimplemented!()
-    }
 }
 
 #[derive(Clone)]
@@ -185,10 +389,6 @@ struct W1{
 
 impl Component for W1{
     type ComponentStorage = DenseComponentStorage<Self>;
-
-    fn update(&mut self) {
-        unimplemented!()
-    }
 }
 
 #[derive(Clone)]
@@ -204,4 +404,7 @@ impl Component for W2{
 }
 
 criterion_group!(benches, ecs_allocate_new_entities_pos_vel, ecs_deallocate_empty_entity, ecs_deallocate_entity_with_component, ecs_register_component, ecs_add_new_component, ecs_remove_component, ecs_fetch_component, ecs_pos_vel_update, ecs_sequential_systems, ecs_parallel_systems);
-criterion_main!(benches);
\ No newline at end of file
+criterion_group!(archetype_benches, archetype_allocate_new_entities_pos_vel, archetype_deallocate_entity_with_component, archetype_add_new_component, archetype_remove_component, archetype_fetch_component, archetype_pos_vel_update, archetype_sequential_systems);
+criterion_group!(inline_benches, inline_pos_vel_update, inline_chunked_pos_vel_update);
+criterion_group!(churn_benches, ecs_churn_maintain, ecs_churn_compact, ecs_churn_update, ecs_churn_update_compacted);
+criterion_main!(benches, archetype_benches, inline_benches, churn_benches);
\ No newline at end of file
@@ -0,0 +1,335 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use downcast_rs::Downcast;
+use component::Component;
+use component::HookContext;
+use entity::EntityIndex;
+use entity::mapping::EntityMap;
+
+///the error returned by the ECS operations that only work on component stores
+pub const UNSUPPORTED: &str = "not supported by the archetype layout";
+
+///how a world lays out its components, chosen when the world is built
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    ///one store per component type indexed by entity, the default
+    Dense,
+    ///entities with the same set of component types share a table holding one column per type
+    ///components are read through ECS::archetypes rather than component handles and have no component events
+    ///handles, cloning, moving, snapshots, saving and checksums fail on these worlds rather than miss the tables
+    Archetype
+}
+
+///one column of a table, a Vec of a single component type behind a type erased interface
+pub trait Column: Downcast + Send + Sync {
+    ///an empty column of the same type
+    fn empty(&self) -> Box<Column>;
+    ///swap remove a row and push it onto another column of the same type
+    fn move_row(&mut self, row: usize, to: &mut Column);
+    ///swap remove a row running its on_remove hook
+    fn remove_row(&mut self, row: usize, entity: EntityIndex, ctx: &mut HookContext);
+    ///release unused capacity
+    fn shrink(&mut self);
+    fn len(&self) -> usize;
+}
+impl_downcast!(Column);
+
+impl<T: Component> Column for Vec<T> {
+    fn empty(&self) -> Box<Column> {
+        Box::new(Vec::<T>::new())
+    }
+
+    fn move_row(&mut self, row: usize, to: &mut Column) {
+        let component = self.swap_remove(row);
+        to.downcast_mut::<Vec<T>>().expect("columns of different types").push(component);
+    }
+
+    fn remove_row(&mut self, row: usize, entity: EntityIndex, ctx: &mut HookContext) {
+        self.swap_remove(row).on_remove(entity, ctx);
+    }
+
+    fn shrink(&mut self) {
+        self.shrink_to_fit()
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+}
+
+///the entities sharing one set of component types, row n of every column belongs to entity n
+pub struct Table {
+    types: Vec<TypeId>,
+    columns: Vec<Box<Column>>,
+    entities: Vec<EntityIndex>
+}
+
+impl Table {
+
+    fn column_index(&self, ty: TypeId) -> Option<usize> {
+        self.types.binary_search(&ty).ok()
+    }
+
+    fn vec<T: Component>(&self) -> Option<&Vec<T>> {
+        let index = self.column_index(TypeId::of::<T>())?;
+        self.columns[index].downcast_ref::<Vec<T>>()
+    }
+
+    fn vec_mut<T: Component>(&mut self) -> Option<&mut Vec<T>> {
+        let index = self.column_index(TypeId::of::<T>())?;
+        self.columns[index].downcast_mut::<Vec<T>>()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    ///the component types stored in this table, sorted
+    pub fn types(&self) -> &[TypeId] {
+        &self.types
+    }
+
+    ///the entity stored in each row
+    pub fn entities(&self) -> &[EntityIndex] {
+        &self.entities
+    }
+
+    pub fn has<T: Component>(&self) -> bool {
+        self.column_index(TypeId::of::<T>()).is_some()
+    }
+
+    pub fn column<T: Component>(&self) -> Option<&[T]> {
+        self.vec::<T>().map(|column| &column[..])
+    }
+
+    pub fn column_mut<T: Component>(&mut self) -> Option<&mut [T]> {
+        self.vec_mut::<T>().map(|column| &mut column[..])
+    }
+
+    ///one column to read alongside another to write, None if either is missing or both are the same type
+    pub fn columns<A: Component, B: Component>(&mut self) -> Option<(&[A], &mut [B])> {
+        let a = self.column_index(TypeId::of::<A>())?;
+        let b = self.column_index(TypeId::of::<B>())?;
+        if a == b {
+            return None;
+        }
+        let (read, write) = pair(&mut self.columns, a, b);
+        Some((&read.downcast_ref::<Vec<A>>()?[..], &mut write.downcast_mut::<Vec<B>>()?[..]))
+    }
+}
+
+///component storage for the archetype layout, an entity moves to another table whenever a component is added or removed
+pub struct Archetypes {
+    tables: Vec<Table>,
+    index: HashMap<Vec<TypeId>, usize>,
+    //table and row of every entity holding at least one component, indexed by entity
+    locations: Vec<Option<(usize, usize)>>
+}
+
+impl Archetypes {
+
+    pub fn new() -> Archetypes {
+        Archetypes{ tables: Vec::new(), index: HashMap::new(), locations: Vec::new() }
+    }
+
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+
+    pub fn tables_mut(&mut self) -> &mut [Table] {
+        &mut self.tables
+    }
+
+    ///the table and row an entity is stored in, None if it has no components
+    pub fn location(&self, entity: EntityIndex) -> Option<(usize, usize)> {
+        match self.locations.get(entity.index()) {
+            Some(&Some((table, row))) if self.tables[table].entities[row] == entity => Some((table, row)),
+            _ => None
+        }
+    }
+
+    pub fn get<T: Component>(&self, entity: EntityIndex) -> Option<&T> {
+        let (table, row) = self.location(entity)?;
+        self.tables[table].vec::<T>().map(|column| &column[row])
+    }
+
+    pub fn get_mut<T: Component>(&mut self, entity: EntityIndex) -> Option<&mut T> {
+        let (table, row) = self.location(entity)?;
+        self.tables[table].vec_mut::<T>().map(|column| &mut column[row])
+    }
+
+    ///store a component against an entity, moving the entity to the table for its new set of types
+    ///a component it already has is replaced in place, running the old one's on_remove hook first
+    pub fn insert<T: Component>(&mut self, entity: EntityIndex, mut component: T, ctx: &mut HookContext) {
+        if let Some(existing) = self.get_mut::<T>(entity) {
+            existing.on_remove(entity, ctx);
+            component.on_add(entity, ctx);
+            *existing = component;
+            return;
+        }
+        component.on_add(entity, ctx);
+        let from = self.location(entity);
+        let mut types = from.map_or(Vec::new(), |(table, _)| self.tables[table].types.clone());
+        let at = types.binary_search(&TypeId::of::<T>()).unwrap_err();
+        types.insert(at, TypeId::of::<T>());
+        let to = self.table(types, from.map(|(table, _)| table), Some(Box::new(Vec::<T>::new())));
+        self.tables[to].vec_mut::<T>().expect("table was built with the column").push(component);
+        self.relocate(entity, from, to);
+    }
+
+    ///take a component off an entity running its on_remove hook, moving the entity to the table for its remaining types
+    pub fn remove<T: Component>(&mut self, entity: EntityIndex, ctx: &mut HookContext) -> Option<T> {
+        let (from, row) = self.location(entity)?;
+        let column = self.tables[from].column_index(TypeId::of::<T>())?;
+        let mut component = self.tables[from].columns[column].downcast_mut::<Vec<T>>().expect("column of the wrong type").swap_remove(row);
+        let mut types = self.tables[from].types.clone();
+        types.remove(column);
+        let to = self.table(types, Some(from), None);
+        self.relocate(entity, Some((from, row)), to);
+        component.on_remove(entity, ctx);
+        Some(component)
+    }
+
+    ///drop every component of an entity running their on_remove hooks
+    pub fn despawn(&mut self, entity: EntityIndex, ctx: &mut HookContext) {
+        if let Some((table, row)) = self.location(entity) {
+            for column in self.tables[table].columns.iter_mut() {
+                column.remove_row(row, entity, ctx);
+            }
+            self.remove_entity_row(table, row);
+            self.locations[entity.index()] = None;
+        }
+    }
+
+    ///release capacity left behind by entities that moved away or were despawned
+    pub fn trim(&mut self) {
+        let len = self.locations.iter().rposition(|l| l.is_some()).map_or(0, |last| last + 1);
+        self.locations.truncate(len);
+        self.locations.shrink_to_fit();
+        for table in self.tables.iter_mut() {
+            table.entities.shrink_to_fit();
+            for column in table.columns.iter_mut() {
+                column.shrink();
+            }
+        }
+    }
+
+    ///rewrite the entity stored in every row after the world's entities were renumbered
+    pub fn remap(&mut self, map: &EntityMap) {
+        let mut locations = Vec::new();
+        for (index, table) in self.tables.iter_mut().enumerate() {
+            for (row, entity) in table.entities.iter_mut().enumerate() {
+                //every row holds a live entity, and compact maps them all
+                *entity = map.get(*entity).unwrap_or(*entity);
+                if locations.len() <= entity.index() {
+                    locations.resize(entity.index() + 1, None);
+                }
+                locations[entity.index()] = Some((index, row));
+            }
+        }
+        self.locations = locations;
+    }
+
+    ///visit every component of one type
+    pub fn for_each<'a, T: Component, F: FnMut(EntityIndex, &'a T)>(&'a self, mut f: F) {
+        for table in self.tables.iter() {
+            if let Some(column) = table.vec::<T>() {
+                for (entity, component) in table.entities.iter().zip(column.iter()) {
+                    f(*entity, component);
+                }
+            }
+        }
+    }
+
+    ///mutably visit every component of one type
+    pub fn for_each_mut<T: Component, F: FnMut(EntityIndex, &mut T)>(&mut self, mut f: F) {
+        for table in self.tables.iter_mut() {
+            let entities = &table.entities;
+            if let Some(index) = table.types.binary_search(&TypeId::of::<T>()).ok() {
+                let column = table.columns[index].downcast_mut::<Vec<T>>().expect("column of the wrong type");
+                for (entity, component) in entities.iter().zip(column.iter_mut()) {
+                    f(*entity, component);
+                }
+            }
+        }
+    }
+
+    ///visit every entity holding both types, reading the first and writing the second
+    pub fn for_each_pair<A: Component, B: Component, F: FnMut(&A, &mut B)>(&mut self, mut f: F) {
+        for table in self.tables.iter_mut() {
+            if let Some((read, write)) = table.columns::<A, B>() {
+                for (a, b) in read.iter().zip(write.iter_mut()) {
+                    f(a, b);
+                }
+            }
+        }
+    }
+
+    //find the table for a set of types, building it from the columns of a table it shares types with and one extra column
+    fn table(&mut self, types: Vec<TypeId>, like: Option<usize>, mut extra: Option<Box<Column>>) -> usize {
+        if let Some(&table) = self.index.get(&types) {
+            return table;
+        }
+        let mut columns = Vec::with_capacity(types.len());
+        for ty in types.iter() {
+            let shared = like.and_then(|like| {
+                let table = &self.tables[like];
+                table.column_index(*ty).map(|index| table.columns[index].empty())
+            });
+            columns.push(shared.or_else(|| extra.take()).expect("no column for component type"));
+        }
+        self.tables.push(Table{ types: types.clone(), columns, entities: Vec::new() });
+        self.index.insert(types, self.tables.len() - 1);
+        self.tables.len() - 1
+    }
+
+    //move the columns the destination shares with the entity's current row, any others must already have been pushed or taken
+    fn relocate(&mut self, entity: EntityIndex, from: Option<(usize, usize)>, to: usize) {
+        if let Some((from, row)) = from {
+            {
+                let (source, target) = pair(&mut self.tables, from, to);
+                for (ty, column) in source.types.iter().zip(source.columns.iter_mut()) {
+                    if let Some(index) = target.column_index(*ty) {
+                        column.move_row(row, &mut *target.columns[index]);
+                    }
+                }
+            }
+            self.remove_entity_row(from, row);
+        }
+        self.tables[to].entities.push(entity);
+        if self.locations.len() <= entity.index() {
+            self.locations.resize(entity.index() + 1, None);
+        }
+        self.locations[entity.index()] = Some((to, self.tables[to].entities.len() - 1));
+    }
+
+    //the columns have already been swap removed, so the last entity now sits in the freed row
+    fn remove_entity_row(&mut self, table: usize, row: usize) {
+        let entities = &mut self.tables[table].entities;
+        entities.swap_remove(row);
+        if let Some(moved) = entities.get(row) {
+            self.locations[moved.index()] = Some((table, row));
+        }
+    }
+}
+
+impl Default for Archetypes {
+    fn default() -> Self {
+        Archetypes::new()
+    }
+}
+
+//two distinct elements of a slice, both mutable
+fn pair<T>(items: &mut [T], a: usize, b: usize) -> (&mut T, &mut T) {
+    if a < b {
+        let (left, right) = items.split_at_mut(b);
+        (&mut left[a], &mut right[0])
+    }else{
+        let (left, right) = items.split_at_mut(a);
+        (&mut right[0], &mut left[b])
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::hash::Hasher;
+use ECS;
+use component::Component;
+use component::Iter;
+use component::ComponentRef;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+///64 bit FNV-1a, unlike the standard library hasher its output is fixed so peers on different builds agree
+pub struct FnvHasher(u64);
+
+impl FnvHasher {
+    pub fn new() -> FnvHasher {
+        FnvHasher(FNV_OFFSET)
+    }
+}
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher::new()
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    //integers are always hashed little endian so peers on big and little endian machines agree
+    fn write_u8(&mut self, i: u8) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    //usize differs in width between peers, always hash it as 64 bits
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i8(&mut self, i: i8) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64);
+    }
+}
+
+///part of the world that differed between two checksum reports
+#[derive(Clone, Debug, PartialEq)]
+pub enum Divergence {
+    ///the set of live entities or their generations differ
+    Entities,
+    ///the components registered under this name differ
+    Component(String),
+    ///only one of the reports has components registered under this name
+    Missing(String)
+}
+
+///per part hashes of a world, exchanged between peers to find where a desync started
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChecksumReport {
+    pub tick: u64,
+    pub entities: u64,
+    pub components: Vec<(String, u64)>
+}
+
+///hashes every live component of one type
+pub type HashFn = fn(&ECS) -> u64;
+
+impl ChecksumReport {
+
+    ///hash a world, components are hashed in name order so HashMap order never leaks in
+    pub(crate) fn new(ecs: &ECS, components: &BTreeMap<String, HashFn>) -> ChecksumReport {
+        ChecksumReport{
+            tick: ecs.current_tick(),
+            entities: hash_entities(ecs),
+            components: components.iter().map(|(name, hash)| (name.clone(), hash(ecs))).collect()
+        }
+    }
+
+    ///single hash of the whole report
+    pub fn total(&self) -> u64 {
+        let mut hasher = FnvHasher::new();
+        self.entities.hash(&mut hasher);
+        self.components.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    ///the first part of the world, in checksum order, that differs from another report
+    pub fn first_divergence(&self, other: &ChecksumReport) -> Option<Divergence> {
+        if self.entities != other.entities {
+            return Some(Divergence::Entities);
+        }
+        let theirs = other.components.iter().cloned().collect::<BTreeMap<_, _>>();
+        for (name, hash) in self.components.iter() {
+            match theirs.get(name) {
+                Some(other_hash) if other_hash == hash => continue,
+                Some(_) => return Some(Divergence::Component(name.clone())),
+                None => return Some(Divergence::Missing(name.clone()))
+            }
+        }
+        let ours = self.components.iter().map(|c| &c.0).collect::<Vec<_>>();
+        other.components.iter().find(|c| !ours.contains(&&c.0)).map(|c| Divergence::Missing(c.0.clone()))
+    }
+}
+
+fn hash_entities(ecs: &ECS) -> u64 {
+    let mut hasher = FnvHasher::new();
+    for (index, entry) in ecs.entity_list.entity_list.iter().enumerate() {
+        if entry.is_live {
+            index.hash(&mut hasher);
+            entry.generation.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+pub fn hash_components<T: Component + Hash>(ecs: &ECS) -> u64 {
+    let mut hasher = FnvHasher::new();
+    if let Ok(store) = ecs.storage.get::<T>() {
+        let handle = store.read_handle();
+        let mut iter = handle.get_iterator();
+        while let Some((component, index)) = iter.next_element(None) {
+            if ecs.entity_list.entity_list.get(index).map_or(false, |e| e.is_live) {
+                index.hash(&mut hasher);
+                component.component().hash(&mut hasher);
+            }
+        }
+    }
+    hasher.finish()
+}
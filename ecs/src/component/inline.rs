@@ -0,0 +1,280 @@
+use std::mem;
+use std::slice;
+use entity::Entity;
+use entity::EntityIndex;
+use entity::Generation;
+use component::Component;
+use component::Storage;
+use component::Iter;
+use component::ChangeTicks;
+
+///storage keeping components by value in one contiguous Vec indexed by entity, empty slots hold T::default()
+///runs of occupied slots can be handed out as plain slices, which hot loops can vectorize
+#[derive(Clone)]
+pub struct InlineComponentStorage<T>{
+    components: Vec<T>,
+    occupied: Vec<bool>,
+    ticks: Vec<ChangeTicks>,
+    //generation of the entity each slot was last stored for
+    generations: Vec<Generation>,
+    tick: u64
+}
+
+impl<T: Component + Default> Default for InlineComponentStorage<T>{
+    fn default() -> Self {
+        InlineComponentStorage::new()
+    }
+}
+
+impl<T: Component + Default> InlineComponentStorage<T> {
+    pub fn new() -> InlineComponentStorage<T> {
+        InlineComponentStorage{ components: Vec::new(), occupied: Vec::new(), ticks: Vec::new(), generations: Vec::new(), tick: 0 }
+    }
+
+    ///every run of consecutive occupied slots, with the entity index of its first component
+    pub fn chunks(&self) -> Chunks<T> {
+        Chunks{ components: &self.components, occupied: &self.occupied, index: 0 }
+    }
+
+    ///every run of consecutive occupied slots mutably, marking each run as changed as it is handed out
+    pub fn chunks_mut(&mut self) -> ChunksMut<T> {
+        self.chunks_mut_recording(None)
+    }
+
+    //chunks_mut for a write handle, which reports the slots handed out as modified when it is dropped
+    pub(crate) fn chunks_mut_recording<'cs>(&'cs mut self, modified: Option<&'cs mut Vec<usize>>) -> ChunksMut<'cs, T> {
+        ChunksMut{ rest: &mut self.components, occupied: &self.occupied, ticks: &mut self.ticks, tick: self.tick, offset: 0, modified }
+    }
+}
+
+impl<'it, T: Component + Default> Storage<'it> for InlineComponentStorage<T> {
+    type Component = T;
+    type Ref = &'it T;
+    type RefMut = &'it mut T;
+    type ComponentIteratorMut = InlineIteratorMut<'it, T>;
+    type ComponentIterator = InlineIterator<'it, T>;
+
+    fn get_component(&self, id: EntityIndex) -> Option<&T> {
+        match self.occupied.get(id.index()) {
+            Some(true) if self.generations[id.index()] == id.generation() => Some(&self.components[id.index()]),
+            _ => None
+        }
+    }
+
+    fn entity(&self, index: usize) -> Option<EntityIndex> {
+        match self.occupied.get(index) {
+            Some(true) => Some(Entity::new(index, self.generations[index])),
+            _ => None
+        }
+    }
+
+    fn remove(&mut self, index: EntityIndex) -> Result<EntityIndex, &str> {
+        if index.index() >= self.len() {
+            Err("index out of bounds")
+        }else if self.generations[index.index()] != index.generation() {
+            Err("incorrect generation")
+        }else{
+            self.take(index);
+            Ok(index)
+        }
+    }
+
+    fn take(&mut self, index: EntityIndex) -> Option<T> {
+        if self.entity(index.index()) != Some(index) {
+            return None;
+        }
+        match self.occupied.get_mut(index.index()) {
+            Some(occupied) if *occupied => {
+                *occupied = false;
+                Some(mem::replace(&mut self.components[index.index()], T::default()))
+            },
+            _ => None
+        }
+    }
+
+    fn get_mut(&mut self, id: EntityIndex) -> Option<&mut T> {
+        if self.entity(id.index()) != Some(id) {
+            return None;
+        }
+        match self.occupied.get(id.index()) {
+            Some(true) => {
+                self.ticks[id.index()].changed = self.tick;
+                Some(&mut self.components[id.index()])
+            },
+            _ => None
+        }
+    }
+
+    fn get_mut_iter(&'it mut self) -> Self::ComponentIteratorMut {
+        InlineIteratorMut{ components: self.components.iter_mut(), occupied: &self.occupied, ticks: &mut self.ticks, tick: self.tick, index: 0 }
+    }
+
+    fn get_iter(&'it self) -> Self::ComponentIterator {
+        InlineIterator{ components: &self.components, occupied: &self.occupied, index: 0 }
+    }
+
+    fn insert(&mut self, index: EntityIndex, component: T) -> Result<EntityIndex, (&'static str, T)> {
+        if index.index() >= Entity::MAX_INDEX {
+            return Err(("entity index is reserved for Entity::DANGLING", component));
+        }
+        while index.index() >= self.len() {
+            self.components.push(T::default());
+            self.occupied.push(false);
+            self.ticks.push(ChangeTicks::default());
+            self.generations.push(0);
+        }
+        if !self.occupied[index.index()] {
+            self.ticks[index.index()].added = self.tick;
+        }
+        self.ticks[index.index()].changed = self.tick;
+        self.components[index.index()] = component;
+        self.occupied[index.index()] = true;
+        self.generations[index.index()] = index.generation();
+        Ok(index)
+    }
+
+    fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+
+    fn ticks(&self) -> &[ChangeTicks] {
+        &self.ticks
+    }
+
+    fn trim(&mut self) {
+        let len = self.occupied.iter().rposition(|o| *o).map_or(0, |last| last + 1);
+        self.components.truncate(len);
+        self.occupied.truncate(len);
+        self.ticks.truncate(len);
+        self.generations.truncate(len);
+        self.components.shrink_to_fit();
+        self.occupied.shrink_to_fit();
+        self.ticks.shrink_to_fit();
+        self.generations.shrink_to_fit();
+    }
+
+    fn move_slot(&mut self, from: usize, to: EntityIndex) {
+        if from < self.len() && to.index() < self.len() {
+            self.components.swap(from, to.index());
+            self.occupied.swap(from, to.index());
+            self.ticks[to.index()] = mem::replace(&mut self.ticks[from], ChangeTicks::default());
+            self.generations[to.index()] = to.generation();
+        }
+    }
+
+    fn heap_size(&self) -> usize {
+        self.components.capacity() * mem::size_of::<T>() + self.occupied.capacity() * mem::size_of::<bool>() + self.ticks.capacity() * mem::size_of::<ChangeTicks>()
+            + self.generations.capacity() * mem::size_of::<Generation>()
+    }
+}
+
+pub struct InlineIterator<'cs, T: 'cs>{
+    components: &'cs [T],
+    occupied: &'cs [bool],
+    index: usize
+}
+
+impl<'cs, T: Component> Iter for InlineIterator<'cs, T> {
+    type Item = &'cs T;
+
+    fn next_element(&mut self, until: Option<usize>) -> Option<(Self::Item, usize)> {
+        let mut index = self.index.max(until.unwrap_or(0));
+        while index < self.components.len() {
+            if self.occupied[index] {
+                self.index = index + 1;
+                return Some((&self.components[index], index));
+            }
+            index += 1;
+        }
+        self.index = index;
+        None
+    }
+}
+
+pub struct InlineIteratorMut<'cs, T: 'cs>{
+    components: slice::IterMut<'cs, T>,
+    occupied: &'cs [bool],
+    ticks: &'cs mut [ChangeTicks],
+    tick: u64,
+    index: usize
+}
+
+impl<'cs, T: Component> Iter for InlineIteratorMut<'cs, T> {
+    type Item = &'cs mut T;
+
+    fn next_element(&mut self, until: Option<usize>) -> Option<(Self::Item, usize)> {
+        let lim = until.unwrap_or(0);
+        if lim > self.index {
+            self.components.nth(lim - self.index - 1);
+            self.index = lim;
+        }
+        loop {
+            let component = self.components.next()?;
+            let index = self.index;
+            self.index += 1;
+            if self.occupied[index] {
+                self.ticks[index].changed = self.tick;
+                return Some((component, index));
+            }
+        }
+    }
+}
+
+///runs of occupied slots in an inline storage, each with the entity index of its first component
+pub struct Chunks<'cs, T: 'cs>{
+    components: &'cs [T],
+    occupied: &'cs [bool],
+    index: usize
+}
+
+impl<'cs, T> Iterator for Chunks<'cs, T> {
+    type Item = (usize, &'cs [T]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, end) = next_run(self.occupied, self.index)?;
+        self.index = end;
+        Some((start, &self.components[start..end]))
+    }
+}
+
+///mutable runs of occupied slots in an inline storage, each with the entity index of its first component
+pub struct ChunksMut<'cs, T: 'cs>{
+    //the slots from offset onwards that have not been handed out yet
+    rest: &'cs mut [T],
+    occupied: &'cs [bool],
+    ticks: &'cs mut [ChangeTicks],
+    tick: u64,
+    offset: usize,
+    modified: Option<&'cs mut Vec<usize>>
+}
+
+impl<'cs, T> Iterator for ChunksMut<'cs, T> {
+    type Item = (usize, &'cs mut [T]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, end) = next_run(self.occupied, self.offset)?;
+        for ticks in self.ticks[start..end].iter_mut() {
+            ticks.changed = self.tick;
+        }
+        if let Some(ref mut modified) = self.modified {
+            modified.extend(start..end);
+        }
+        let offset = self.offset;
+        let rest = mem::replace(&mut self.rest, &mut []);
+        let (run, rest) = rest.split_at_mut(end - offset);
+        self.rest = rest;
+        self.offset = end;
+        Some((start, &mut run[start - offset..]))
+    }
+}
+
+//bounds of the first run of occupied slots at or after from
+fn next_run(occupied: &[bool], from: usize) -> Option<(usize, usize)> {
+    let start = from + occupied[from..].iter().position(|o| *o)?;
+    let end = start + occupied[start..].iter().position(|o| !*o).unwrap_or(occupied.len() - start);
+    Some((start, end))
+}
@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 use std::any::TypeId;
+use std::any::Any;
+use entity::Entity;
 use entity::EntityIndex;
+use entity::Generation;
 use core::borrow::BorrowMut;
 use std::slice;
 use downcast_rs::Downcast;
@@ -9,38 +12,201 @@ use std::sync::RwLockWriteGuard;
 use std::sync::RwLockReadGuard;
 use std::ops::Deref;
 use std::ops::DerefMut;
-
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::mem;
+use ECS;
+use crossbeam::thread;
+use std::any::type_name;
+use std::time::Duration;
+use lock;
+use lock::AccessError;
+use lock::BorrowTracker;
+use lock::BorrowToken;
+use event::EventChannel;
+use event::ComponentEvent;
+use component::registry::ComponentRegistry;
+use component::inline::InlineComponentStorage;
+use component::inline::ChunksMut;
+
+pub mod registry;
+pub mod inline;
+
+///exclusive access to one component store, components mutated through it are reported as modified once the handle is dropped
+///hooks run as components are inserted and removed, but the commands they defer wait for the next ECS call that applies them,
+///call ECS::apply_hook_commands after dropping the handle to run them straight away
+//the store itself is only reachable read-only, every write goes through the handle so hooks, events and ticks stay in step
 pub struct ComponentWriteHandle<'l, T>{
-    pub w: RwLockWriteGuard<'l, T>
+    w: RwLockWriteGuard<'l, T>,
+    events: &'l EventChannel<ComponentEvent>,
+    shared: &'l StoreShared,
+    //slots handed out mutably, only recorded while someone is subscribed to the store's events
+    modified: Option<Vec<usize>>,
+    owner: fn(&T, usize) -> Option<EntityIndex>,
+    _borrow: BorrowToken<'l>
 }
 
-impl<'a, 'b, S: Storage<'b>> ComponentWriteHandle<'a, S>{
-    pub fn get(&'b self, id: EntityIndex) -> &ComponentEntry<S::Component> {
+impl<'a, T: Component> ComponentWriteHandle<'a, DenseComponentStorage<T>>{
+    pub fn get(&self, id: EntityIndex) -> &ComponentEntry<T> {
         self.w.deref().get(id)
     }
+}
 
-    pub fn get_mut_iter(&'b mut self) -> S::ComponentIteratorMut {
-        self.w.deref_mut().get_mut_iter()
+impl<'a, T: Component + Default> ComponentWriteHandle<'a, InlineComponentStorage<T>>{
+    ///every run of consecutive occupied slots mutably, marking each run as changed as it is handed out
+    pub fn chunks_mut(&mut self) -> ChunksMut<T> {
+        let ComponentWriteHandle{ ref mut w, ref mut modified, .. } = *self;
+        w.deref_mut().chunks_mut_recording(modified.as_mut())
     }
 }
 
+impl<'a, 'b, S: Storage<'b>> ComponentWriteHandle<'a, S>{
+    ///the component stored for an entity, whatever storage the component type uses, None if the handle is stale
+    pub fn get_component(&self, id: EntityIndex) -> Option<&S::Component> {
+        self.w.deref().get_component(id)
+    }
+
+    ///iterate mutably, every component handed out is marked as changed
+    pub fn get_mut_iter(&'b mut self) -> Tracked<'b, S::ComponentIteratorMut> {
+        let ComponentWriteHandle{ ref mut w, ref mut modified, .. } = *self;
+        Tracked{ it: w.deref_mut().get_mut_iter(), modified: modified.as_mut() }
+    }
+
+    ///mutable access to a single entity's component, marking it as changed, None if the handle is stale
+    pub fn get_mut(&mut self, id: EntityIndex) -> Option<&mut S::Component> {
+        let ComponentWriteHandle{ ref mut w, ref mut modified, .. } = *self;
+        let component = w.deref_mut().get_mut(id)?;
+        if let Some(modified) = modified.as_mut() {
+            modified.push(id.index());
+        }
+        Some(component)
+    }
+
+    ///store a component against an entity, running its hooks, returns whether it was stored
+    ///refused for Entity::DANGLING and if the slot holds another generation's component, the handle can not see the allocator
+    ///so it is up to the caller to pass a live entity, ECS::add_component checks that
+    pub fn insert(&mut self, id: EntityIndex, component: S::Component) -> bool {
+        match self.w.entity(id.index()) {
+            Some(owner) if owner != id => false,
+            _ => {
+                self.flush_modified();
+                insert_component(self.w.deref_mut(), self.events, self.shared, id, component).is_ok()
+            }
+        }
+    }
+
+    ///take the component off an entity, returns false if there was none or the handle is stale
+    pub fn remove(&mut self, id: EntityIndex) -> bool {
+        self.flush_modified();
+        remove_component(self.w.deref_mut(), self.events, self.shared, id)
+    }
+}
+
+impl<'l, T> Deref for ComponentWriteHandle<'l, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.w.deref()
+    }
+}
+
+impl<'l, T> ComponentWriteHandle<'l, T> {
+    //one Modified event per component handed out mutably since the last flush, however many times it was
+    //flushed before inserts and removes too, so subscribers see events in the order things happened
+    fn flush_modified(&mut self) {
+        if let Some(ref mut modified) = self.modified {
+            modified.sort();
+            modified.dedup();
+            for index in modified.drain(..) {
+                if let Some(entity) = (self.owner)(&self.w, index) {
+                    self.events.emit(ComponentEvent::Modified(entity));
+                }
+            }
+        }
+    }
+}
+
+impl<'l, T> Drop for ComponentWriteHandle<'l, T> {
+    fn drop(&mut self) {
+        self.flush_modified();
+    }
+}
+
+//the owner lookup of a store, kept as a function so the write handle's Drop needs no Storage bound
+fn slot_owner<'st, S: Storage<'st>>(storage: &S, index: usize) -> Option<EntityIndex> {
+    storage.entity(index)
+}
+
 pub struct ComponentReadHandle<'l, T> {
-    pub r: RwLockReadGuard<'l, T>
+    pub r: RwLockReadGuard<'l, T>,
+    _borrow: BorrowToken<'l>
 }
 
-impl<'a, 'b, S:Storage<'b>> ComponentReadHandle<'a, S>{
-    pub fn get(&'b self, id: EntityIndex) -> &ComponentEntry<S::Component> {
+impl<'a, T: Component> ComponentReadHandle<'a, DenseComponentStorage<T>>{
+    pub fn get(&self, id: EntityIndex) -> &ComponentEntry<T> {
         self.r.deref().get(id)
     }
+}
+
+impl<'a, 'b, S:Storage<'b>> ComponentReadHandle<'a, S>{
+    ///the component stored for an entity, whatever storage the component type uses, None if the handle is stale
+    pub fn get_component(&self, id: EntityIndex) -> Option<&S::Component> {
+        self.r.deref().get_component(id)
+    }
 
     pub fn get_iterator(&'b self) -> S::ComponentIterator {
         self.r.deref().get_iter()
     }
+
+    ///iterate over the components that were mutably accessed after the tick `since`
+    pub fn get_changed_iterator(&'b self, since: u64) -> Changed<'b, S::ComponentIterator> {
+        Changed::new(self.r.deref().get_iter(), self.r.deref().ticks(), since)
+    }
+
+    ///iterate over the components that were added after the tick `since`
+    pub fn get_added_iterator(&'b self, since: u64) -> Added<'b, S::ComponentIterator> {
+        Added::new(self.r.deref().get_iter(), self.r.deref().ticks(), since)
+    }
+}
+
+impl<'l, T> Deref for ComponentReadHandle<'l, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.r.deref()
+    }
 }
 
 pub trait Component: 'static + Sized + Send + Sync + Clone{
     type ComponentStorage: for<'st> Storage<'st, Component = Self>;
-    fn update(&mut self);
+    ///per component behaviour, run on every live component of this type by ECS::update_components and ECS::update_all
+    fn update(&mut self) {}
+    ///called just before the component is stored against an entity
+    fn on_add(&mut self, _entity: EntityIndex, _ctx: &mut HookContext) {}
+    ///called once the component has been taken off an entity, just before it is dropped
+    fn on_remove(&mut self, _entity: EntityIndex, _ctx: &mut HookContext) {}
+}
+
+///deferred change to the ECS queued by a component hook
+pub type HookCommand = Box<FnOnce(&mut ECS) + Send>;
+
+///handed to component hooks, commands pushed here run against the ECS once the triggering operation finishes
+pub struct HookContext {
+    commands: Vec<HookCommand>
+}
+
+impl HookContext {
+    pub fn new() -> HookContext {
+        HookContext{ commands: Vec::new() }
+    }
+
+    ///queue a command to run against the ECS after the current operation
+    pub fn defer<F: FnOnce(&mut ECS) + Send + 'static>(&mut self, command: F) {
+        self.commands.push(Box::new(command));
+    }
 }
 
 #[derive(Clone)]
@@ -58,114 +224,510 @@ impl<T: Sized + Send + Sync + Clone> ComponentEntry<T> {
     }
 }
 
+///world ticks at which a component slot was last filled and last mutably accessed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChangeTicks {
+    pub added: u64,
+    pub changed: u64
+}
+
+///a reference to a component as yielded by a storage's iterators, &Box<T> for dense storage and &T for inline storage
+pub trait ComponentRef<T> {
+    fn component(&self) -> &T;
+}
+
+///a mutable reference to a component as yielded by a storage's mutable iterators
+pub trait ComponentRefMut<T>: ComponentRef<T> {
+    fn component_mut(&mut self) -> &mut T;
+}
+
+impl<'a, T> ComponentRef<T> for &'a Box<T> {
+    fn component(&self) -> &T {
+        self
+    }
+}
+
+impl<'a, T> ComponentRef<T> for &'a T {
+    fn component(&self) -> &T {
+        self
+    }
+}
+
+impl<'a, T> ComponentRef<T> for &'a mut Box<T> {
+    fn component(&self) -> &T {
+        self
+    }
+}
+
+impl<'a, T> ComponentRefMut<T> for &'a mut Box<T> {
+    fn component_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+impl<'a, T> ComponentRef<T> for &'a mut T {
+    fn component(&self) -> &T {
+        self
+    }
+}
+
+impl<'a, T> ComponentRefMut<T> for &'a mut T {
+    fn component_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
 pub trait Storage<'st>: 'static + Send + Sync + Clone + Default {
-    type Component: 'static + Send + Sync + Sized + Clone;
-    type ComponentIteratorMut: Iter<Item = &'st mut Box<Self::Component>>;
-    type ComponentIterator: Iter<Item = &'st Box<Self::Component>>;
-    fn get(&self, id: EntityIndex) -> &ComponentEntry<Self::Component>;
+    type Component: Component;
+    type Ref: ComponentRef<Self::Component>;
+    type RefMut: ComponentRefMut<Self::Component>;
+    type ComponentIteratorMut: Iter<Item = Self::RefMut>;
+    type ComponentIterator: Iter<Item = Self::Ref>;
+    ///the component stored for an entity, None if there is none or the slot belongs to another generation
+    fn get_component(&self, id: EntityIndex) -> Option<&Self::Component>;
+    ///the entity whose component is stored in a slot, None if the slot is empty
+    fn entity(&self, index: usize) -> Option<EntityIndex>;
     fn remove(&mut self, EntityIndex) -> Result<EntityIndex, &str>;
+    ///remove the component stored for an entity and hand it back, None if there is none or the slot belongs to another generation
+    fn take(&mut self, index: EntityIndex) -> Option<Self::Component>;
+    ///mutable access to the component stored for an entity, marking it as changed, None for a stale handle like get_component
+    fn get_mut(&mut self, id: EntityIndex) -> Option<&mut Self::Component>;
     fn get_mut_iter(&'st mut self) -> Self::ComponentIteratorMut;
     fn get_iter(&'st self) -> Self::ComponentIterator;
 
-    fn insert(&mut self, index: EntityIndex, component: Self::Component) -> Result<EntityIndex, &str>;
+    ///a refused component is handed back with the reason
+    fn insert(&mut self, index: EntityIndex, component: Self::Component) -> Result<EntityIndex, (&'static str, Self::Component)>;
     fn len(&self) -> usize;
+    ///set the tick stamped onto components that are inserted or mutably accessed
+    fn set_tick(&mut self, tick: u64);
+    ///change ticks of every slot, indexed by entity
+    fn ticks(&self) -> &[ChangeTicks];
+    ///drop the empty slots after the last stored component and release unused capacity
+    fn trim(&mut self);
+    ///move the component in one slot, with its change ticks, into an empty slot for the entity's new handle without running hooks
+    fn move_slot(&mut self, from: usize, to: EntityIndex);
+    ///approximate bytes allocated by the storage, including boxed components
+    fn heap_size(&self) -> usize;
 }
 
 pub trait GenericComponentStorage: Send + Sync + Downcast{
     fn remove(&mut self, index: EntityIndex) -> Result<EntityIndex, &str>;
+    ///call update on every stored component, taking the write lock for the duration
+    fn update(&self);
+    ///store a copy of one entity's component against another entity, returns false if the source has none
+    fn clone_component(&mut self, from: EntityIndex, to: EntityIndex) -> bool;
+    ///a copy of the stored components, shared with the previous snapshot if the store has not been written to since
+    fn snapshot(&self) -> Arc<Any + Send + Sync>;
+    ///replace the stored components with a snapshot, None empties the store, no hooks run and no events are emitted
+    fn restore(&mut self, snapshot: Option<&Arc<Any + Send + Sync>>);
+    ///take an entity's component off it running its on_remove hook, boxed so it can be stored in another world
+    fn take_boxed(&mut self, index: EntityIndex) -> Option<Box<Any + Send>>;
+    ///store a component taken with take_boxed against an entity, fails if it is of the wrong type or the storage refuses it
+    ///a refused component is handed back with the reason so it can be put back where it came from
+    fn insert_boxed(&mut self, index: EntityIndex, component: Box<Any + Send>) -> Result<(), (&'static str, Box<Any + Send>)>;
+    fn trim(&mut self);
+    fn move_slot(&mut self, from: usize, to: EntityIndex);
+    fn heap_size(&self) -> usize;
 }
 impl_downcast!(GenericComponentStorage);
 
-pub struct ComponentStore<T>(pub RwLock<T>);
+///state shared between the component storage and every store registered with it
+pub struct StoreShared {
+    tick: AtomicU64,
+    commands: Mutex<Vec<HookCommand>>
+}
+
+impl StoreShared {
+    fn new() -> StoreShared {
+        StoreShared{ tick: AtomicU64::new(1), commands: Mutex::new(Vec::new()) }
+    }
+
+    fn tick(&self) -> u64 {
+        self.tick.load(Ordering::SeqCst)
+    }
+
+    fn queue(&self, ctx: HookContext) {
+        if !ctx.commands.is_empty() {
+            self.commands.lock().expect("poisoned lock").extend(ctx.commands);
+        }
+    }
+}
+
+//counts write access to a store so snapshots of a store that has not changed can share the previous copy
+struct SnapshotCache<T> {
+    version: AtomicU64,
+    last: Mutex<Option<(u64, Arc<T>)>>
+}
+
+impl<T> SnapshotCache<T> {
+    fn new() -> SnapshotCache<T> {
+        SnapshotCache{ version: AtomicU64::new(0), last: Mutex::new(None) }
+    }
+
+    fn touch(&self) -> u64 {
+        self.version.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+pub struct ComponentStore<T>(pub RwLock<T>, pub EventChannel<ComponentEvent>, Arc<StoreShared>, BorrowTracker, SnapshotCache<T>);
 
 //switch RwLockWriteGuard to ComponentWrite/Read Handle.
 impl<'st, T: Storage<'st>> ComponentStore<T> {
+    #[track_caller]
     pub fn write_handle(&self) -> ComponentWriteHandle<T>{
-        let result = self.0.write().unwrap();
-        ComponentWriteHandle{ w: result }
+        let (mut result, borrow) = lock::write(&self.0, &self.3, type_name::<T::Component>());
+        self.4.touch();
+        result.set_tick(self.2.tick());
+        self.handle(result, borrow)
     }
 
+    #[track_caller]
     pub fn read_handle(&self) -> ComponentReadHandle<T>{
-        let result = self.0.read().unwrap();
-        ComponentReadHandle{ r: result }
+        let (result, borrow) = lock::read(&self.0, &self.3, type_name::<T::Component>());
+        ComponentReadHandle{ r: result, _borrow: borrow }
+    }
+
+    ///get a write handle if the store is free, without blocking
+    #[track_caller]
+    pub fn try_write_handle(&self) -> Result<ComponentWriteHandle<T>, AccessError>{
+        self.write_handle_within(None)
+    }
+
+    ///get a read handle if the store is not being written to, without blocking
+    #[track_caller]
+    pub fn try_read_handle(&self) -> Result<ComponentReadHandle<T>, AccessError>{
+        self.read_handle_within(None)
+    }
+
+    ///wait up to timeout for a write handle, None tries exactly once
+    #[track_caller]
+    pub fn write_handle_within(&self, timeout: Option<Duration>) -> Result<ComponentWriteHandle<T>, AccessError>{
+        let (mut result, borrow) = lock::write_within(&self.0, &self.3, timeout, type_name::<T::Component>())?;
+        self.4.touch();
+        result.set_tick(self.2.tick());
+        Ok(self.handle(result, borrow))
+    }
+
+    fn handle<'l>(&'l self, w: RwLockWriteGuard<'l, T>, borrow: BorrowToken<'l>) -> ComponentWriteHandle<'l, T> {
+        let modified = if self.1.subscriber_count() > 0 { Some(Vec::new()) } else { None };
+        ComponentWriteHandle{ w, events: &self.1, shared: &self.2, modified, owner: slot_owner::<T>, _borrow: borrow }
+    }
+
+    ///wait up to timeout for a read handle, None tries exactly once
+    #[track_caller]
+    pub fn read_handle_within(&self, timeout: Option<Duration>) -> Result<ComponentReadHandle<T>, AccessError>{
+        let (result, borrow) = lock::read_within(&self.0, &self.3, timeout, type_name::<T::Component>())?;
+        Ok(ComponentReadHandle{ r: result, _borrow: borrow })
+    }
+
+    ///describe which threads and call sites currently hold this store, always empty in release builds
+    pub fn borrow_report(&self) -> String {
+        self.3.report(type_name::<T::Component>())
     }
 
     pub fn get_mut_handle(&mut self) -> &mut T {
-        self.0.get_mut().unwrap()
+        self.split().0
+    }
+
+    //the storage with its tick brought up to date, alongside what is needed to run hooks and emit events
+    fn split(&mut self) -> (&mut T, &EventChannel<ComponentEvent>, &StoreShared) {
+        self.4.touch();
+        let storage = lock::get_mut(&mut self.0);
+        storage.set_tick(self.2.tick());
+        (storage, &self.1, &self.2)
+    }
+
+    ///receive an event every time a component in this store is inserted, removed or modified
+    pub fn subscribe(&self) -> Receiver<ComponentEvent> {
+        self.1.subscribe()
     }
+
+    ///store a component against an entity, running the on_add hook and the on_remove hook of any component it replaces
+    pub fn insert(&mut self, index: EntityIndex, component: T::Component) -> Result<EntityIndex, &str> {
+        let (storage, events, shared) = self.split();
+        insert_component(storage, events, shared, index, component).map_err(|e| e.0)?;
+        Ok(index)
+    }
+
+    ///take the component off an entity running its on_remove hook, returns false if there was none
+    pub fn remove(&mut self, index: EntityIndex) -> bool {
+        let (storage, events, shared) = self.split();
+        remove_component(storage, events, shared, index)
+    }
+}
+
+//shared by stores and write handles so both run hooks and emit events the same way
+//a component the storage refuses is handed back without its on_add hook having run
+fn insert_component<'st, S: Storage<'st>>(storage: &mut S, events: &EventChannel<ComponentEvent>, shared: &StoreShared, index: EntityIndex, mut component: S::Component) -> Result<(), (&'static str, S::Component)> {
+    let mut ctx = HookContext::new();
+    //a replacement is written over the old component in place, so it keeps the tick it was added at
+    let event = match storage.get_mut(index) {
+        Some(old) => {
+            old.on_remove(index, &mut ctx);
+            component.on_add(index, &mut ctx);
+            *old = component;
+            ComponentEvent::Modified(index)
+        },
+        None => {
+            storage.insert(index, component)?;
+            storage.get_mut(index).expect("component was just inserted").on_add(index, &mut ctx);
+            ComponentEvent::Inserted(index)
+        }
+    };
+    shared.queue(ctx);
+    events.emit(event);
+    Ok(())
+}
+
+fn remove_component<'st, S: Storage<'st>>(storage: &mut S, events: &EventChannel<ComponentEvent>, shared: &StoreShared, index: EntityIndex) -> bool {
+    take_component(storage, events, shared, index).is_some()
 }
 
-impl<'cs, T: 'static + Storage<'cs>> GenericComponentStorage for ComponentStore<T> {
-    fn remove(&mut self, index: (usize, u64)) -> Result<(usize, u64), &str> {
-        self.0.get_mut().expect("poisoned lock").remove(index)
+fn take_component<'st, S: Storage<'st>>(storage: &mut S, events: &EventChannel<ComponentEvent>, shared: &StoreShared, index: EntityIndex) -> Option<S::Component> {
+    let mut ctx = HookContext::new();
+    match storage.take(index) {
+        Some(mut old) => {
+            old.on_remove(index, &mut ctx);
+            shared.queue(ctx);
+            events.emit(ComponentEvent::Removed(index));
+            Some(old)
+        },
+        None => None
+    }
+}
+
+impl<T: 'static + for<'cs> Storage<'cs>> GenericComponentStorage for ComponentStore<T> {
+    fn remove(&mut self, index: EntityIndex) -> Result<EntityIndex, &str> {
+        ComponentStore::remove(self, index);
+        Ok(index)
+    }
+
+    fn update(&self) {
+        let mut handle = self.write_handle();
+        let mut it = handle.get_mut_iter();
+        while let Some((mut component, _)) = it.next_element(None) {
+            component.component_mut().update();
+        }
+    }
+
+    fn clone_component(&mut self, from: EntityIndex, to: EntityIndex) -> bool {
+        let component = match lock::get_mut(&mut self.0).get_component(from) {
+            Some(component) => component.clone(),
+            None => return false
+        };
+        ComponentStore::insert(self, to, component).expect("unable to insert component");
+        true
+    }
+
+    fn snapshot(&self) -> Arc<Any + Send + Sync> {
+        let handle = self.read_handle();
+        //writers bump the version while holding the write lock, so it can not move while we hold the read lock
+        let version = self.4.version.load(Ordering::SeqCst);
+        let mut last = self.4.last.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((cached, ref snapshot)) = *last {
+            if cached == version {
+                return snapshot.clone();
+            }
+        }
+        let snapshot = Arc::new(handle.r.clone());
+        *last = Some((version, snapshot.clone()));
+        snapshot
+    }
+
+    fn restore(&mut self, snapshot: Option<&Arc<Any + Send + Sync>>) {
+        let version = self.4.touch();
+        let restored = match snapshot.and_then(|s| s.clone().downcast::<T>().ok()) {
+            Some(snapshot) => {
+                let restored = (*snapshot).clone();
+                *self.4.last.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some((version, snapshot));
+                restored
+            },
+            None => T::default()
+        };
+        *lock::get_mut(&mut self.0) = restored;
+    }
+
+    fn take_boxed(&mut self, index: EntityIndex) -> Option<Box<Any + Send>> {
+        let (storage, events, shared) = self.split();
+        match take_component(storage, events, shared, index) {
+            Some(component) => Some(Box::new(component)),
+            None => None
+        }
+    }
+
+    fn insert_boxed(&mut self, index: EntityIndex, component: Box<Any + Send>) -> Result<(), (&'static str, Box<Any + Send>)> {
+        let component = component.downcast::<<T as Storage>::Component>().map_err(|component| ("downcast failed, type error", component))?;
+        let (storage, events, shared) = self.split();
+        insert_component(storage, events, shared, index, *component).map_err(|(e, component)| (e, Box::new(component) as Box<Any + Send>))
+    }
+
+    fn trim(&mut self) {
+        self.split().0.trim()
+    }
+
+    fn move_slot(&mut self, from: usize, to: EntityIndex) {
+        self.split().0.move_slot(from, to)
+    }
+
+    fn heap_size(&self) -> usize {
+        self.read_handle().heap_size()
     }
 }
 
 #[derive(Clone)]
-pub struct DenseComponentStorage<T: Send + Sync + Clone>(Vec<ComponentEntry<T>>);
+pub struct DenseComponentStorage<T: Send + Sync + Clone>{
+    entries: Vec<ComponentEntry<T>>,
+    ticks: Vec<ChangeTicks>,
+    //generation of the entity each slot was last stored for
+    generations: Vec<Generation>,
+    tick: u64
+}
 
 impl<T: Component> Default for DenseComponentStorage<T>{
     fn default() -> Self {
-        DenseComponentStorage(Vec::new())
+        DenseComponentStorage::new()
     }
 }
 
 impl<'it, T: Component> Storage<'it> for DenseComponentStorage<T> {
     type Component = T;
+    type Ref = &'it Box<T>;
+    type RefMut = &'it mut Box<T>;
     type ComponentIteratorMut = ComponentIteratorMut<'it, T>;
     type ComponentIterator = ComponentIterator<'it, T>;
 
-    //potentially make this return a result type
-    fn get(&self, id: (usize, u64)) -> &ComponentEntry<Self::Component> {
-        if let Some(x) = self.0.get(id.0) {
-            x
-        }else{
-            &ComponentEntry::Empty
+    fn get_component(&self, id: EntityIndex) -> Option<&Self::Component> {
+        match self.entries.get(id.index()) {
+            Some(ComponentEntry::Entry(component)) if self.generations[id.index()] == id.generation() => Some(component),
+            _ => None
         }
     }
 
-    fn remove(&mut self, index: EntityIndex) -> Result<(usize, u64), &str> {
-        if let Some(reference) = self.0.get_mut(index.0){
-            *reference = ComponentEntry::Empty;
-            Ok(index)
-        }else{
+    fn entity(&self, index: usize) -> Option<EntityIndex> {
+        match self.entries.get(index) {
+            Some(ComponentEntry::Entry(_)) => Some(Entity::new(index, self.generations[index])),
+            _ => None
+        }
+    }
+
+    fn remove(&mut self, index: EntityIndex) -> Result<EntityIndex, &str> {
+        if index.index() >= self.len() {
             Err("index out of bounds")
+        }else if self.generations[index.index()] != index.generation() {
+            Err("incorrect generation")
+        }else{
+            self.entries[index.index()] = ComponentEntry::Empty;
+            Ok(index)
+        }
+    }
+
+    fn take(&mut self, index: EntityIndex) -> Option<Self::Component> {
+        if self.entity(index.index()) != Some(index) {
+            return None;
+        }
+        match self.entries.get_mut(index.index()) {
+            Some(reference) => match mem::replace(reference, ComponentEntry::Empty) {
+                ComponentEntry::Entry(component) => Some(*component),
+                ComponentEntry::Empty => None
+            },
+            None => None
+        }
+    }
+
+    fn get_mut(&mut self, id: EntityIndex) -> Option<&mut Self::Component> {
+        if self.entity(id.index()) != Some(id) {
+            return None;
+        }
+        match self.entries.get_mut(id.index()) {
+            Some(ComponentEntry::Entry(component)) => {
+                self.ticks[id.index()].changed = self.tick;
+                Some(component)
+            },
+            _ => None
         }
     }
 
     fn get_mut_iter(&'it mut self) -> Self::ComponentIteratorMut {
-        ComponentIteratorMut{current_index: 0, st: self.0.iter_mut()}
+        ComponentIteratorMut{current_index: 0, st: self.entries.iter_mut(), ticks: &mut self.ticks, tick: self.tick}
     }
 
     fn get_iter(&'it self) -> Self::ComponentIterator {
-        ComponentIterator{ st: self.0.iter(), current_index: 0 }
+        ComponentIterator{ st: self.entries.iter(), current_index: 0 }
     }
 
-    fn insert(&mut self, index: (usize, u64), component: Self::Component) -> Result<EntityIndex, &str>{
-        if index.0 > self.len(){
-            while index.0 > self.len() {
-                self.0.push(ComponentEntry::Empty);
-            }
-            self.0.push(ComponentEntry::Entry(Box::new(component)));
-        }else{
-            self.0.insert(index.0, ComponentEntry::Entry(Box::new(component)));
+    fn insert(&mut self, index: EntityIndex, component: Self::Component) -> Result<EntityIndex, (&'static str, Self::Component)>{
+        if index.index() >= Entity::MAX_INDEX {
+            return Err(("entity index is reserved for Entity::DANGLING", component));
+        }
+        while index.index() >= self.len() {
+            self.entries.push(ComponentEntry::Empty);
+            self.ticks.push(ChangeTicks::default());
+            self.generations.push(0);
+        }
+        if let ComponentEntry::Empty = self.entries[index.index()] {
+            self.ticks[index.index()].added = self.tick;
         }
+        self.ticks[index.index()].changed = self.tick;
+        self.generations[index.index()] = index.generation();
+        self.entries[index.index()] = ComponentEntry::Entry(Box::new(component));
         Ok(index)
     }
 
     fn len(&self) -> usize {
-        self.0.len()
+        self.entries.len()
+    }
+
+    fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+
+    fn ticks(&self) -> &[ChangeTicks] {
+        &self.ticks
+    }
+
+    fn trim(&mut self) {
+        let len = self.entries.iter().rposition(|e| if let ComponentEntry::Entry(_) = e { true } else { false }).map_or(0, |last| last + 1);
+        self.entries.truncate(len);
+        self.ticks.truncate(len);
+        self.generations.truncate(len);
+        self.entries.shrink_to_fit();
+        self.ticks.shrink_to_fit();
+        self.generations.shrink_to_fit();
+    }
+
+    fn move_slot(&mut self, from: usize, to: EntityIndex) {
+        if from < self.len() && to.index() < self.len() {
+            self.entries.swap(from, to.index());
+            self.ticks[to.index()] = mem::replace(&mut self.ticks[from], ChangeTicks::default());
+            self.generations[to.index()] = to.generation();
+        }
+    }
+
+    fn heap_size(&self) -> usize {
+        let boxed = self.entries.iter().filter(|e| if let ComponentEntry::Entry(_) = e { true } else { false }).count();
+        self.entries.capacity() * mem::size_of::<ComponentEntry<T>>() + boxed * mem::size_of::<T>() + self.ticks.capacity() * mem::size_of::<ChangeTicks>()
+            + self.generations.capacity() * mem::size_of::<Generation>()
     }
 }
 
 impl<'it, T: Send + Sync + Clone> DenseComponentStorage<T> {
     pub fn new() -> DenseComponentStorage<T>{
-        DenseComponentStorage(Vec::new())
+        DenseComponentStorage{ entries: Vec::new(), ticks: Vec::new(), generations: Vec::new(), tick: 0 }
+    }
+
+    //potentially make this return a result type
+    pub fn get(&self, id: EntityIndex) -> &ComponentEntry<T> {
+        match self.entries.get(id.index()) {
+            Some(x) if self.generations[id.index()] == id.generation() => x,
+            _ => &ComponentEntry::Empty
+        }
     }
 }
 
 pub struct ComponentStorage(
-    HashMap<TypeId, Box<GenericComponentStorage>>
+    HashMap<TypeId, Box<GenericComponentStorage>>,
+    Arc<StoreShared>,
+    ComponentRegistry
 );
 //I think here i need to store a Box any and store vectors in the any
 //this will allow to downcast to a Vec<T> and subsequently get the appropriate iterator.
@@ -173,13 +735,35 @@ pub struct ComponentStorage(
 impl<'st> ComponentStorage {
 
     pub fn new() -> ComponentStorage {
-        ComponentStorage(HashMap::new())
+        ComponentStorage::with_registry(ComponentRegistry::new())
+    }
+
+    ///storage sharing its component types with every other storage using the registry, stores for the types already known are created
+    pub fn with_registry(registry: ComponentRegistry) -> ComponentStorage {
+        let mut storage = ComponentStorage(HashMap::new(), Arc::new(StoreShared::new()), registry.clone());
+        storage.install(&registry);
+        storage
+    }
+
+    ///the registry component types are recorded in as they are registered
+    pub fn registry(&self) -> &ComponentRegistry {
+        &self.2
+    }
+
+    ///create a store for every type known to the registry that this storage does not have yet
+    pub fn install(&mut self, registry: &ComponentRegistry) {
+        for (id, register) in registry.entries() {
+            if !self.0.contains_key(&id) {
+                register(self).expect("component was not registered");
+            }
+        }
     }
 
     pub fn register_component<T: Component>(&mut self) -> Result<(usize), &str>{
-        let compstrg: DenseComponentStorage<T> = DenseComponentStorage::new();
-        let len = compstrg.0.len();
-        let componentstore = ComponentStore(RwLock::new(compstrg));
+        self.2.register::<T>();
+        let compstrg = T::ComponentStorage::default();
+        let len = compstrg.len();
+        let componentstore = ComponentStore(RwLock::new(compstrg), EventChannel::new(), self.1.clone(), BorrowTracker::new(), SnapshotCache::new());
         if let None = self.0.insert(TypeId::of::<T>(), Box::new(componentstore)) {
             Ok(len)
         }else{
@@ -187,31 +771,29 @@ impl<'st> ComponentStorage {
         }
     }
 
-    pub fn add_component<T: Component>(&mut self, component: T, id: EntityIndex) -> Result<EntityIndex, &str> {
+    pub fn add_component<T: Component>(&mut self, component: T, id: EntityIndex) -> Result<EntityIndex, &'static str> {
         if let Ok(storage) = self.get_mut::<T>(){
-            let mut store = storage.0.get_mut().unwrap();
-            store.insert(id, component).expect("unable to insert component");
+            let (storage, events, shared) = storage.split();
+            insert_component(storage, events, shared, id, component).map_err(|e| e.0)?;
             Ok(id)
         }else{
             Err("component is not registered")
         }
     }
 
-    pub fn remove_component<T: Component>(&mut self, id: EntityIndex) -> Result<EntityIndex, &str>{
+    pub fn remove_component<T: Component>(&mut self, id: EntityIndex) -> Result<EntityIndex, &'static str>{
         if let Ok(storage) = self.get_mut::<T>(){
-            let mut store = storage.0.get_mut().unwrap();
-            if id.0 >= store.len() {
-                Err("entity does not have component")
-            }else{
-                store.remove(id).expect("unable to remove component");
-                Ok(id)
+            if id.index() >= storage.get_mut_handle().len() {
+                return Err("entity does not have component");
             }
+            storage.remove(id);
+            Ok(id)
         }else{
             Err("component is not registered")
         }
     }
 
-    pub fn clear_entity(&mut self, id: EntityIndex) -> Result<(), &str> {
+    pub fn clear_entity(&mut self, id: EntityIndex) -> Result<(), &'static str> {
         let mut status = Ok(());
         for (_, cs) in self.0.borrow_mut() {
             if let Ok(_) = cs.remove(id) {
@@ -224,6 +806,79 @@ impl<'st> ComponentStorage {
         status
     }
 
+    ///trim every store down to its last stored component and release unused capacity
+    pub fn trim(&mut self) {
+        for store in self.0.values_mut() {
+            store.trim();
+        }
+    }
+
+    ///move every component in one entity slot into an empty slot for the entity's new handle, without running hooks or emitting events
+    pub fn move_slot(&mut self, from: usize, to: EntityIndex) {
+        for store in self.0.values_mut() {
+            store.move_slot(from, to);
+        }
+    }
+
+    ///approximate bytes allocated by every store
+    pub fn heap_size(&self) -> usize {
+        self.0.values().map(|store| store.heap_size()).sum()
+    }
+
+    ///copy every component one entity has onto another except the listed types, running on_add hooks for the copies
+    pub fn clone_entity(&mut self, from: EntityIndex, to: EntityIndex, except: &[TypeId]) {
+        for (ty, store) in self.0.iter_mut() {
+            if !except.contains(ty) {
+                store.clone_component(from, to);
+            }
+        }
+    }
+
+    ///whether there is a store for every one of these types
+    pub fn has_stores<'t, I: IntoIterator<Item = &'t TypeId>>(&self, types: I) -> bool {
+        types.into_iter().all(|ty| self.0.contains_key(ty))
+    }
+
+    ///take every component off an entity running their on_remove hooks, keyed by component type
+    pub fn take_entity(&mut self, id: EntityIndex) -> Vec<(TypeId, Box<Any + Send>)> {
+        self.0.iter_mut().filter_map(|(ty, store)| store.take_boxed(id).map(|component| (*ty, component))).collect()
+    }
+
+    ///store a component taken with take_entity against an entity, running its on_add hook, a refused component is handed back
+    pub fn insert_boxed(&mut self, ty: TypeId, id: EntityIndex, component: Box<Any + Send>) -> Result<(), (&'static str, Box<Any + Send>)> {
+        match self.0.get_mut(&ty) {
+            Some(store) => store.insert_boxed(id, component),
+            None => Err(("unregistered type", component))
+        }
+    }
+
+    ///run update on every component of type T
+    pub fn update_component<T: Component>(&self) -> Result<(), &str> {
+        match self.0.get(&TypeId::of::<T>()) {
+            Some(store) => {
+                store.update();
+                Ok(())
+            },
+            None => Err("unregistered type")
+        }
+    }
+
+    ///run update on every component of every registered type, one type after another
+    pub fn update_all(&self) {
+        for store in self.0.values() {
+            store.update();
+        }
+    }
+
+    ///run update on every component of every registered type, each type on its own thread
+    pub fn update_all_parallel(&self) {
+        thread::scope(|scope| {
+            for store in self.0.values() {
+                scope.spawn(move |_| store.update());
+            }
+        }).expect("component update panicked");
+    }
+
     pub fn get<T: Component>(&self) -> Result<&ComponentStore<T::ComponentStorage>, &str> {
         if let Some(x) = self.0.get(&TypeId::of::<T>()){
             if let Some(dc) = x.downcast_ref::<ComponentStore<T::ComponentStorage>>() {
@@ -252,6 +907,43 @@ impl<'st> ComponentStorage {
         self.0.len()
     }
 
+    ///the tick currently stamped onto inserted and mutably accessed components
+    pub fn current_tick(&self) -> u64 {
+        self.1.tick()
+    }
+
+    ///put the world tick back, used when restoring a snapshot
+    pub fn set_tick(&self, tick: u64) {
+        self.1.tick.store(tick, Ordering::SeqCst)
+    }
+
+    ///share the contents of every store, stores that have not been written to since the last snapshot are not copied again
+    pub fn snapshot(&self) -> HashMap<TypeId, Arc<Any + Send + Sync>> {
+        self.0.iter().map(|(id, store)| (*id, store.snapshot())).collect()
+    }
+
+    ///put every store back to a snapshot, stores registered after the snapshot was taken are emptied
+    pub fn restore(&mut self, snapshot: &HashMap<TypeId, Arc<Any + Send + Sync>>) {
+        for (id, store) in self.0.iter_mut() {
+            store.restore(snapshot.get(id));
+        }
+    }
+
+    ///move on to the next tick, returning it
+    pub fn advance_tick(&self) -> u64 {
+        self.1.tick.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    ///queue commands collected by hooks run outside of the component stores, such as by archetype tables
+    pub fn queue_hook_commands(&self, ctx: HookContext) {
+        self.1.queue(ctx)
+    }
+
+    ///drain the commands queued by component hooks since the last call
+    pub fn take_hook_commands(&self) -> Vec<HookCommand> {
+        mem::replace(&mut *self.1.commands.lock().expect("poisoned lock"), Vec::new())
+    }
+
 //    pub fn get_mut_iterator<T: Component>(&mut self) -> Result<T, &str>{
 //        if let Ok(entry) = self.get_mut::<T>(){
 //            let mut storage = entry.write_handle();
@@ -310,6 +1002,8 @@ impl<H: Iter, T: Iter> Iter for ComponentIteratorJoin<H, T> {
 
 pub struct ComponentIteratorMut<'cs, T: 'cs + Send + Sync + Clone>{
     st: slice::IterMut<'cs, ComponentEntry<T>>,
+    ticks: &'cs mut [ChangeTicks],
+    tick: u64,
     current_index: usize
 }
 
@@ -332,7 +1026,10 @@ impl<'it, T: Component> Iter for ComponentIteratorMut<'it, T>{
             }
 
             match r {
-                Some(ComponentEntry::Entry(ref mut v)) => {return Some((v, i))},
+                Some(ComponentEntry::Entry(ref mut v)) => {
+                    self.ticks[i].changed = self.tick;
+                    return Some((v, i))
+                },
                 Some(_) => continue,
                 None => {return None}
             }
@@ -387,6 +1084,94 @@ impl<'cs, T: Component> Iter for ComponentIterator<'cs, T> {
     }
 }
 
+///filters an iterator down to the components mutably accessed after a given tick
+pub struct Changed<'t, H>{
+    it: H,
+    ticks: &'t [ChangeTicks],
+    since: u64
+}
+
+impl<'t, H: Iter> Changed<'t, H> {
+    pub fn new(it: H, ticks: &'t [ChangeTicks], since: u64) -> Changed<'t, H> {
+        Changed{ it, ticks, since }
+    }
+}
+
+impl<'t, H: Iter> Iter for Changed<'t, H> {
+    type Item = H::Item;
+
+    fn next_element(&mut self, until: Option<usize>) -> Option<(Self::Item, usize)> {
+        let mut next = self.it.next_element(until);
+        while let Some((_, i)) = next {
+            if self.ticks[i].changed > self.since {
+                break;
+            }
+            next = self.it.next_element(None);
+        }
+        next
+    }
+}
+
+///filters an iterator down to the components added after a given tick
+pub struct Added<'t, H>{
+    it: H,
+    ticks: &'t [ChangeTicks],
+    since: u64
+}
+
+impl<'t, H: Iter> Added<'t, H> {
+    pub fn new(it: H, ticks: &'t [ChangeTicks], since: u64) -> Added<'t, H> {
+        Added{ it, ticks, since }
+    }
+}
+
+impl<'t, H: Iter> Iter for Added<'t, H> {
+    type Item = H::Item;
+
+    fn next_element(&mut self, until: Option<usize>) -> Option<(Self::Item, usize)> {
+        let mut next = self.it.next_element(until);
+        while let Some((_, i)) = next {
+            if self.ticks[i].added > self.since {
+                break;
+            }
+            next = self.it.next_element(None);
+        }
+        next
+    }
+}
+
+///a mutable iterator handed out by a write handle, recording the slots it hands out so the handle can report them as modified
+pub struct Tracked<'t, H>{
+    it: H,
+    modified: Option<&'t mut Vec<usize>>
+}
+
+impl<'t, H: Iter> Iter for Tracked<'t, H> {
+    type Item = H::Item;
+
+    fn next_element(&mut self, until: Option<usize>) -> Option<(Self::Item, usize)> {
+        let next = self.it.next_element(until);
+        if let (Some((_, i)), Some(ref mut modified)) = (next.as_ref(), self.modified.as_mut()) {
+            modified.push(*i);
+        }
+        next
+    }
+}
+
+impl<'t, H> Deref for Tracked<'t, H> {
+    type Target = H;
+
+    fn deref(&self) -> &H {
+        &self.it
+    }
+}
+
+impl<'t, H> DerefMut for Tracked<'t, H> {
+    fn deref_mut(&mut self) -> &mut H {
+        &mut self.it
+    }
+}
+
 pub struct IteratorWrapper<H>(H);
 
 impl<H> Iterator for IteratorWrapper<H> where H: Iter{
@@ -409,10 +1194,6 @@ pub struct StubPosition{
 
 impl Component for StubPosition{
     type ComponentStorage = DenseComponentStorage<Self>;
-
-    fn update(&mut self) {
-        unimplemented!()
-    }
 }
 
 #[derive(Clone)]
@@ -423,8 +1204,4 @@ pub struct StubVelocity{
 
 impl Component for StubVelocity{
     type ComponentStorage = DenseComponentStorage<Self>;
-
-    fn update(&mut self) {
-        unimplemented!()
-    }
 }
\ No newline at end of file
@@ -0,0 +1,53 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::RwLock;
+use component::Component;
+use component::ComponentStorage;
+
+///creates the store for one component type in a component storage
+pub type RegisterFn = fn(&mut ComponentStorage) -> Result<usize, &str>;
+
+///the component types known to a group of worlds, cloning it gives another handle to the same registry
+///a world built with a handle starts with a store for every type registered through any of the others
+#[derive(Clone, Default)]
+pub struct ComponentRegistry(Arc<RwLock<HashMap<TypeId, RegisterFn>>>);
+
+impl ComponentRegistry {
+
+    pub fn new() -> ComponentRegistry {
+        ComponentRegistry(Arc::new(RwLock::new(HashMap::new())))
+    }
+
+    ///record a component type, returns false if it was already known
+    pub fn register<T: Component>(&self) -> bool {
+        let mut types = self.0.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if types.contains_key(&TypeId::of::<T>()) {
+            return false;
+        }
+        types.insert(TypeId::of::<T>(), ComponentStorage::register_component::<T>);
+        true
+    }
+
+    pub fn contains(&self, id: TypeId) -> bool {
+        self.0.read().unwrap_or_else(|poisoned| poisoned.into_inner()).contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.read().unwrap_or_else(|poisoned| poisoned.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///check whether both handles refer to the same registry
+    pub fn shares_with(&self, other: &ComponentRegistry) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    ///every known type with the function creating its store, copied out so the registry is not locked while stores are created
+    pub fn entries(&self) -> Vec<(TypeId, RegisterFn)> {
+        self.0.read().unwrap_or_else(|poisoned| poisoned.into_inner()).iter().map(|(id, register)| (*id, *register)).collect()
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use serde_value::Value;
+use ECS;
+use entity::EntityIndex;
+use entity::management::EntityAllocator;
+use serialize::ComponentData;
+use serialize::SerializeError;
+use serialize::TypeRegistry;
+use serialize::WorldData;
+use entity::mapping::EntityMap;
+
+///the changes that turn one world state into another, small enough to send every tick
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorldDelta {
+    ///entities live in the new state that were not, or had another generation, in the old one
+    pub spawned: Vec<EntityIndex>,
+    ///entities live in the old state that are gone from the new one, their components go with them
+    pub despawned: Vec<EntityIndex>,
+    ///components an entity gained, keyed by registered component name
+    pub added: BTreeMap<String, ComponentData>,
+    ///components whose value is different in the new state
+    pub changed: BTreeMap<String, ComponentData>,
+    ///components taken off entities that are still live
+    pub removed: BTreeMap<String, Vec<EntityIndex>>
+}
+
+impl WorldDelta {
+
+    ///compare two saved world states, entities are matched by index and generation and components by value
+    pub fn between(old: &WorldData, new: &WorldData) -> WorldDelta {
+        let before = live(&old.entities);
+        let after = live(&new.entities);
+        let mut delta = WorldDelta::default();
+        delta.spawned = after.difference(&before).cloned().collect();
+        delta.despawned = before.difference(&after).cloned().collect();
+        let empty = Vec::new();
+        let names = old.components.keys().chain(new.components.keys()).collect::<BTreeSet<_>>();
+        for name in names {
+            let previous = old.components.get(name).unwrap_or(&empty).iter().cloned().collect::<HashMap<EntityIndex, Value>>();
+            let current = new.components.get(name).unwrap_or(&empty);
+            let mut added = Vec::new();
+            let mut changed = Vec::new();
+            for (entity, value) in current.iter() {
+                match previous.get(entity) {
+                    Some(old_value) if old_value == value => {},
+                    Some(_) => changed.push((*entity, value.clone())),
+                    None => added.push((*entity, value.clone()))
+                }
+            }
+            let current = current.iter().map(|c| c.0).collect::<BTreeSet<_>>();
+            let mut removed = previous.keys().filter(|e| !current.contains(e) && after.contains(e)).cloned().collect::<Vec<_>>();
+            removed.sort();
+            if !added.is_empty() {
+                delta.added.insert(name.clone(), added);
+            }
+            if !changed.is_empty() {
+                delta.changed.insert(name.clone(), changed);
+            }
+            if !removed.is_empty() {
+                delta.removed.insert(name.clone(), removed);
+            }
+        }
+        delta
+    }
+
+    ///check whether the two states were the same
+    pub fn is_empty(&self) -> bool {
+        self.spawned.is_empty() && self.despawned.is_empty() && self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    ///bring a saved world state in the old state up to the new one
+    pub fn apply_to(&self, data: &mut WorldData) {
+        for entity in self.despawned.iter() {
+            let _ = data.entities.deallocate(*entity);
+            for components in data.components.values_mut() {
+                components.retain(|c| c.0 != *entity);
+            }
+        }
+        for entity in self.spawned.iter() {
+            let _ = data.entities.mirror_at(*entity);
+        }
+        for (name, entities) in self.removed.iter() {
+            if let Some(components) = data.components.get_mut(name) {
+                components.retain(|c| !entities.contains(&c.0));
+            }
+        }
+        for (name, updates) in self.added.iter().chain(self.changed.iter()) {
+            let components = data.components.entry(name.clone()).or_insert_with(Vec::new);
+            for (entity, value) in updates {
+                match components.iter_mut().find(|c| c.0 == *entity) {
+                    Some(existing) => existing.1 = value.clone(),
+                    None => components.push((*entity, value.clone()))
+                }
+            }
+        }
+    }
+
+    ///like apply, but spawned entities are allocated fresh and recorded in map, which translates every other entity in the delta
+    ///components registered as mapped have their entity references translated as well
+    pub fn apply_mapped(&self, registry: &TypeRegistry, ecs: &mut ECS, map: &mut EntityMap) -> Result<(), SerializeError> {
+        self.check(registry)?;
+        for entity in self.despawned.iter() {
+            if let Some(local) = map.remove(*entity) {
+                ecs.deallocate_entity(local).map_err(|e| SerializeError::Decode(e.to_string()))?;
+            }
+        }
+        for entity in self.spawned.iter() {
+            let local = ecs.allocate_new_entity();
+            map.insert(*entity, local);
+        }
+        for (name, entities) in self.removed.iter() {
+            let remove = registry.remover(name).expect("component was checked above");
+            for entity in entities {
+                if let Some(local) = map.get(*entity) {
+                    remove(ecs, local);
+                }
+            }
+        }
+        for (name, components) in self.added.iter().chain(self.changed.iter()) {
+            let load = registry.loader(name).expect("component was checked above");
+            load(ecs, components.clone(), Some(map))?;
+        }
+        Ok(())
+    }
+
+    ///bring an ECS that is in the old state up to the new one, entities keep the indices they had in the world the delta came from
+    pub fn apply(&self, registry: &TypeRegistry, ecs: &mut ECS) -> Result<(), SerializeError> {
+        self.check(registry)?;
+        for entity in self.despawned.iter() {
+            ecs.deallocate_entity(*entity).map_err(|e| SerializeError::Decode(e.to_string()))?;
+        }
+        for entity in self.spawned.iter() {
+            ecs.allocate_entity_at(*entity).map_err(|e| SerializeError::Decode(e.to_string()))?;
+        }
+        for (name, entities) in self.removed.iter() {
+            let remove = registry.remover(name).expect("component was checked above");
+            for entity in entities {
+                remove(ecs, *entity);
+            }
+        }
+        for (name, components) in self.added.iter().chain(self.changed.iter()) {
+            let insert = registry.inserter(name).expect("component was checked above");
+            for (entity, value) in components {
+                insert(ecs, *entity, value.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check(&self, registry: &TypeRegistry) -> Result<(), SerializeError> {
+        for name in self.added.keys().chain(self.changed.keys()).chain(self.removed.keys()) {
+            if !registry.has_component(name) {
+                return Err(SerializeError::Unregistered(name.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn live(entities: &EntityAllocator) -> BTreeSet<EntityIndex> {
+    entities.live().collect()
+}
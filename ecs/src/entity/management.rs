@@ -1,17 +1,54 @@
 use super::*;
 use core::slice;
+use std::mem;
+use std::collections::VecDeque;
 use component::Iter;
+use entity::mapping::EntityMap;
 
 //entry to define an allocation into a generational data structure
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Entry {
     pub is_live: bool,
     pub generation: u64
 }
 
+///which freed index the allocator hands out next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ReusePolicy {
+    ///the most recently freed index, keeping the entity list short
+    Lifo,
+    ///the index that has been free the longest, so a stale handle is only ever compared against a much later entity
+    Fifo,
+    ///the index that has been free the longest, but only once at least this many indices are free, new indices are pushed until then
+    Threshold(usize)
+}
+
+impl Default for ReusePolicy {
+    fn default() -> Self {
+        ReusePolicy::Lifo
+    }
+}
+
 //the reason for this abstraction is to allow for the Iterator trait to be implemented on this data structure. easily...
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EntityAllocator {
     pub entity_list: Vec<Entry>,
-    pub free_list: Vec<usize>,
+    pub free_list: VecDeque<usize>,
+    ///generation given to newly pushed slots, above every generation of slots that were trimmed off or renumbered
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub base_generation: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub policy: ReusePolicy,
+    ///slots freed at this generation are retired, they are never handed out again so no handle can be reissued
+    #[cfg_attr(feature = "serde", serde(default = "max_generation"))]
+    pub generation_limit: u64
+}
+
+fn max_generation() -> u64 {
+    Entity::MAX_GENERATION
 }
 
 impl EntityAllocator {
@@ -19,27 +56,106 @@ impl EntityAllocator {
     pub fn new() -> EntityAllocator {
         EntityAllocator{
             entity_list: Vec::new(),
-            free_list: Vec::new()
+            free_list: VecDeque::new(),
+            base_generation: 0,
+            policy: ReusePolicy::Lifo,
+            generation_limit: max_generation()
+        }
+    }
+
+    pub fn with_policy(policy: ReusePolicy) -> EntityAllocator {
+        let mut allocator = EntityAllocator::new();
+        allocator.policy = policy;
+        allocator
+    }
+
+    ///change which freed index is handed out next, indices already free are kept
+    pub fn set_policy(&mut self, policy: ReusePolicy) {
+        self.policy = policy;
+    }
+
+    ///retire slots once they have been freed at this generation, indices already free at or past it are retired immediately
+    pub fn set_generation_limit(&mut self, limit: u64) -> Result<(), &'static str> {
+        if limit > Entity::MAX_GENERATION {
+            return Err("generation limit is past Entity::MAX_GENERATION");
+        }
+        self.generation_limit = limit;
+        let entity_list = &self.entity_list;
+        self.free_list.retain(|i| entity_list[*i].generation < limit);
+        Ok(())
+    }
+
+    ///slots that will never be handed out again
+    pub fn retired(&self) -> usize {
+        self.entity_list.iter().filter(|e| self.is_retired(e)).count()
+    }
+
+    fn is_retired(&self, entry: &Entry) -> bool {
+        !entry.is_live && entry.generation >= self.generation_limit
+    }
+
+    fn reuse(&mut self) -> Option<usize> {
+        match self.policy {
+            ReusePolicy::Lifo => self.free_list.pop_back(),
+            ReusePolicy::Fifo => self.free_list.pop_front(),
+            ReusePolicy::Threshold(min) if self.free_list.len() >= min.max(1) => self.free_list.pop_front(),
+            ReusePolicy::Threshold(_) => None
         }
     }
 
     pub fn allocate(&mut self) -> EntityIndex {
-        if let Some(x) = self.free_list.pop() {
+        if let Some(x) = self.reuse() {
             let mut index = &mut self.entity_list[x];
             index.is_live = true;
             index.generation += 1;
-            (x, index.generation)
+            Entity::new(x, index.generation)
         }else{
-            self.entity_list.push(Entry { is_live: true, generation: 0 });
-            (self.entity_list.len() - 1, 0)
+            assert!(self.entity_list.len() < Entity::MAX_INDEX, "ran out of entity indices");
+            self.entity_list.push(Entry { is_live: true, generation: self.base_generation });
+            Entity::new(self.entity_list.len() - 1, self.base_generation)
+        }
+    }
+
+    ///allocate one particular entity, used to mirror entities allocated by another ECS
+    ///a slot only takes a generation newer than the ones it has held, and none once it is retired, so no stale handle comes back to life
+    pub fn allocate_at(&mut self, id: EntityIndex) -> Result<EntityIndex, &'static str> {
+        match self.entity_list.get(id.index()) {
+            Some(entry) if entry.is_live => return Err("entity is already live"),
+            Some(entry) if self.is_retired(entry) => return Err("entity index is retired"),
+            Some(entry) if id.generation() <= entry.generation => return Err("generation is not newer than the slot's"),
+            //slots pushed from here on start at base_generation, older generations may have been handed out before a trim
+            None if id.generation() < self.base_generation => return Err("generation is not newer than the slot's"),
+            _ => {}
         }
+        self.mirror_at(id)?;
+        Ok(id)
     }
 
-    pub fn deallocate(&mut self, id: EntityIndex) -> Result<(), &str> {
-        if id.1 == self.entity_list[id.0].generation {
-            if self.entity_list[id.0].is_live {
-                self.entity_list[id.0].is_live = false;
-                self.free_list.push(id.0);
+    ///make the entity live whatever its slot held before, for states that only follow another allocator,
+    ///where an entity hidden from the state and shown again comes back at the generation it left with
+    pub(crate) fn mirror_at(&mut self, id: EntityIndex) -> Result<(), &'static str> {
+        if id.index() >= Entity::MAX_INDEX {
+            return Err("entity index is reserved for Entity::DANGLING");
+        }
+        while self.entity_list.len() <= id.index() {
+            self.free_list.push_back(self.entity_list.len());
+            self.entity_list.push(Entry { is_live: false, generation: self.base_generation });
+        }
+        if self.entity_list[id.index()].is_live {
+            return Err("entity is already live");
+        }
+        self.entity_list[id.index()] = Entry { is_live: true, generation: id.generation() };
+        self.free_list.retain(|i| *i != id.index());
+        Ok(())
+    }
+
+    pub fn deallocate(&mut self, id: EntityIndex) -> Result<(), &'static str> {
+        if id.generation() == self.entity_list[id.index()].generation {
+            if self.entity_list[id.index()].is_live {
+                self.entity_list[id.index()].is_live = false;
+                if id.generation() < self.generation_limit {
+                    self.free_list.push_back(id.index());
+                }
                 Ok(())
             } else {
                 Err("already deallocated")
@@ -49,6 +165,87 @@ impl EntityAllocator {
         }
     }
 
+    ///check an allocator read from outside, such as a save file, can hand out entities without reissuing or overflowing a handle
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.entity_list.len() > Entity::MAX_INDEX {
+            return Err("entity list does not fit the handle");
+        }
+        if self.generation_limit > Entity::MAX_GENERATION || self.base_generation > Entity::MAX_GENERATION
+            || self.entity_list.iter().any(|e| e.generation > Entity::MAX_GENERATION) {
+            return Err("generation does not fit the handle");
+        }
+        let mut free = vec![false; self.entity_list.len()];
+        for index in self.free_list.iter() {
+            match self.entity_list.get(*index) {
+                None => return Err("free list points past the entity list"),
+                Some(entry) if entry.is_live => return Err("free list points at a live entity"),
+                Some(entry) if entry.generation >= self.generation_limit => return Err("free list points at a retired slot"),
+                Some(_) if free[*index] => return Err("free list holds an index more than once"),
+                Some(_) => free[*index] = true
+            }
+        }
+        Ok(())
+    }
+
+    ///whether the handle refers to a live entity, false for stale generations and indices past the end of the list
+    pub fn is_live(&self, id: EntityIndex) -> bool {
+        self.entity_list.get(id.index()).map_or(false, |entry| entry.is_live && entry.generation == id.generation())
+    }
+
+    ///the handle of the live entity at an index, None if the slot is free
+    pub fn entity(&self, index: usize) -> Option<EntityIndex> {
+        self.entity_list.get(index).filter(|entry| entry.is_live).map(|entry| Entity::new(index, entry.generation))
+    }
+
+    ///every live entity in index order
+    pub fn live(&self) -> impl Iterator<Item = EntityIndex> + '_ {
+        self.entity_list.iter().enumerate().filter(|(_, entry)| entry.is_live).map(|(index, entry)| Entity::new(index, entry.generation))
+    }
+
+    ///drop the dead slots after the last live entity and release unused capacity, returning how many slots were dropped
+    ///indices handed out again later start above the generations the dropped slots reached, so stale handles stay invalid
+    pub fn trim(&mut self) -> usize {
+        //retired slots stay, dropping them would let their index be pushed again at a lower generation
+        let live = self.entity_list.iter().rposition(|e| e.is_live || self.is_retired(e)).map_or(0, |last| last + 1);
+        let trimmed = self.entity_list.len() - live;
+        for entry in self.entity_list.drain(live..) {
+            self.base_generation = self.base_generation.max(entry.generation + 1);
+        }
+        self.free_list.retain(|i| *i < live);
+        self.entity_list.shrink_to_fit();
+        self.free_list.shrink_to_fit();
+        trimmed
+    }
+
+    ///renumber the live entities into the lowest indices, keeping their order, and drop every other slot
+    ///every entity gets a generation no handle has had before, so the returned map is the only way to find them again
+    ///once no such generation is left below the generation limit entities keep their slots and the map sends each to itself
+    pub fn compact(&mut self) -> EntityMap {
+        //retired slots count too, they are dropped below and their old handles must not match a renumbered entity
+        let generation = self.entity_list.iter().map(|e| e.generation + 1).fold(self.base_generation, u64::max);
+        let mut map = EntityMap::new();
+        //base_generation has to stay a generation that can still be handed out and freed for slots pushed afterwards
+        if generation >= self.generation_limit {
+            for entity in self.live() {
+                map.insert(entity, entity);
+            }
+            return map;
+        }
+        let old = mem::replace(&mut self.entity_list, Vec::new());
+        for (index, entry) in old.into_iter().enumerate().filter(|(_, e)| e.is_live) {
+            map.insert(Entity::new(index, entry.generation), Entity::new(self.entity_list.len(), generation));
+            self.entity_list.push(Entry { is_live: true, generation });
+        }
+        self.free_list = VecDeque::new();
+        self.base_generation = generation + 1;
+        map
+    }
+
+    ///approximate bytes allocated by the entity and free lists
+    pub fn heap_size(&self) -> usize {
+        self.entity_list.capacity() * mem::size_of::<Entry>() + self.free_list.capacity() * mem::size_of::<usize>()
+    }
+
     pub fn get_iter_live(&self) -> EntityIteratorLive{
         EntityIteratorLive{
             st: self.entity_list.iter(),
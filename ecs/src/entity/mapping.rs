@@ -0,0 +1,73 @@
+use super::*;
+use std::collections::HashMap;
+
+///old to new entity indices for entities brought in from a save file or another ECS
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EntityMap(HashMap<EntityIndex, EntityIndex>);
+
+impl EntityMap {
+
+    pub fn new() -> EntityMap {
+        EntityMap(HashMap::new())
+    }
+
+    pub fn insert(&mut self, old: EntityIndex, new: EntityIndex) {
+        self.0.insert(old, new);
+    }
+
+    ///forget an imported entity, returning what it was mapped to
+    pub fn remove(&mut self, old: EntityIndex) -> Option<EntityIndex> {
+        self.0.remove(&old)
+    }
+
+    ///the entity an imported entity became, None if it was not part of the import
+    pub fn get(&self, old: EntityIndex) -> Option<EntityIndex> {
+        self.0.get(&old).cloned()
+    }
+
+    ///the entity an imported entity became, entities that were not part of the import become Entity::DANGLING
+    //passing them through unchanged would let them alias whatever entity has that handle in the destination
+    pub fn map(&self, entity: EntityIndex) -> EntityIndex {
+        self.get(entity).unwrap_or(Entity::DANGLING)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&EntityIndex, &EntityIndex)> {
+        self.0.iter()
+    }
+}
+
+///implemented by components that hold references to other entities so the references survive a merge
+///references to entities outside the merge are replaced with Entity::DANGLING
+pub trait MapEntities {
+    fn map_entities(&mut self, map: &EntityMap);
+}
+
+impl MapEntities for EntityIndex {
+    fn map_entities(&mut self, map: &EntityMap) {
+        *self = map.map(*self);
+    }
+}
+
+impl<T: MapEntities> MapEntities for Option<T> {
+    fn map_entities(&mut self, map: &EntityMap) {
+        if let Some(inner) = self {
+            inner.map_entities(map);
+        }
+    }
+}
+
+impl<T: MapEntities> MapEntities for Vec<T> {
+    fn map_entities(&mut self, map: &EntityMap) {
+        for inner in self.iter_mut() {
+            inner.map_entities(map);
+        }
+    }
+}
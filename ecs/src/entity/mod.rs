@@ -1,5 +1,100 @@
 pub mod management;
-pub type Entity = usize;
+pub mod mapping;
+use std::fmt;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+#[cfg(feature = "serde")]
+use serde::Serializer;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Deserializer;
+#[cfg(feature = "serde")]
+use serde::de::Error;
+
 pub type Generation = u64;
-pub type EntityIndex = (Entity, Generation);
+pub type EntityIndex = Entity;
+
+///bits of a handle given to the index, the rest hold the generation
+///32 by default, the index-bits-24, index-bits-40 and index-bits-48 features change it, the largest enabled wins
+#[cfg(feature = "index-bits-48")]
+pub const INDEX_BITS: u32 = 48;
+#[cfg(all(feature = "index-bits-40", not(feature = "index-bits-48")))]
+pub const INDEX_BITS: u32 = 40;
+#[cfg(all(feature = "index-bits-24", not(any(feature = "index-bits-40", feature = "index-bits-48"))))]
+pub const INDEX_BITS: u32 = 24;
+#[cfg(not(any(feature = "index-bits-24", feature = "index-bits-40", feature = "index-bits-48")))]
+pub const INDEX_BITS: u32 = 32;
+
+pub const GENERATION_BITS: u32 = 64 - INDEX_BITS;
+
+///an entity handle, the index and generation packed into one u64 so a handle is only built by the ECS that allocated it
+//the index sits in the high bits so handles order by index and then by generation
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity(u64);
+
+impl Entity {
+
+    pub const MAX_INDEX: usize = ((1u64 << INDEX_BITS) - 1) as usize;
+    pub const MAX_GENERATION: Generation = (1u64 << GENERATION_BITS) - 1;
+    ///a handle no allocator hands out, for references to entities that do not exist, allocators stop one index short of it
+    pub const DANGLING: Entity = Entity(u64::MAX);
+
+    //only allocators and the stores recording which entity owns a slot make handles
+    pub(crate) fn new(index: usize, generation: Generation) -> Entity {
+        assert!(index <= Entity::MAX_INDEX, "entity index does not fit the handle");
+        assert!(generation <= Entity::MAX_GENERATION, "entity generation does not fit the handle");
+        Entity(((index as u64) << GENERATION_BITS) | generation)
+    }
+
+    pub fn index(&self) -> usize {
+        (self.0 >> GENERATION_BITS) as usize
+    }
+
+    pub fn generation(&self) -> Generation {
+        self.0 & Entity::MAX_GENERATION
+    }
+
+    ///the packed handle, for sending across a boundary that only takes integers
+    pub fn to_bits(&self) -> u64 {
+        self.0
+    }
+
+    ///a handle from a value given out by to_bits, kept out of the public api so a handle to a live entity can not be forged from an integer
+    //nothing outside the tests rebuilds a handle from bits, saves and packets go through Deserialize which checks the split
+    #[cfg(test)]
+    pub(crate) fn from_bits(bits: u64) -> Entity {
+        Entity(bits)
+    }
+}
+
+impl fmt::Display for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}v{}", self.index(), self.generation())
+    }
+}
+
+impl fmt::Debug for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Entity({})", self)
+    }
+}
+
+//saved as an index and generation pair so saves do not depend on the bit split
+#[cfg(feature = "serde")]
+impl Serialize for Entity {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.index(), self.generation()).serialize(serializer)
+    }
+}
 
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Entity {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Entity, D::Error> {
+        let (index, generation) = <(usize, Generation)>::deserialize(deserializer)?;
+        if index > Entity::MAX_INDEX || generation > Entity::MAX_GENERATION {
+            return Err(D::Error::custom(format!("entity {}v{} does not fit the handle", index, generation)));
+        }
+        Ok(Entity::new(index, generation))
+    }
+}
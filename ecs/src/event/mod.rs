@@ -0,0 +1,60 @@
+use std::sync::Mutex;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::Receiver;
+use entity::EntityIndex;
+
+///emitted by the ECS when an entity is allocated, deallocated or renumbered
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntityEvent {
+    Allocated(EntityIndex),
+    Deallocated(EntityIndex),
+    ///compact moved a live entity from the first handle to the second
+    Remapped(EntityIndex, EntityIndex)
+}
+
+///emitted by a component store when a component of its type changes on an entity
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ComponentEvent {
+    Inserted(EntityIndex),
+    Removed(EntityIndex),
+    Modified(EntityIndex)
+}
+
+///fan out channel, every subscriber receives its own copy of each emitted event
+pub struct EventChannel<E> {
+    subscribers: Mutex<Vec<Sender<E>>>
+}
+
+impl<E: Clone> EventChannel<E> {
+
+    pub fn new() -> EventChannel<E> {
+        EventChannel{ subscribers: Mutex::new(Vec::new()) }
+    }
+
+    ///register a new subscriber, events emitted from now on are queued on the returned receiver
+    pub fn subscribe(&self) -> Receiver<E> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().expect("poisoned lock").push(sender);
+        receiver
+    }
+
+    ///send an event to every subscriber, subscribers whose receiver has been dropped are removed
+    pub fn emit(&self, event: E) {
+        let mut subscribers = self.subscribers.lock().expect("poisoned lock");
+        if subscribers.is_empty() {
+            return;
+        }
+        subscribers.retain(|s| s.send(event.clone()).is_ok());
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().expect("poisoned lock").len()
+    }
+}
+
+impl<E: Clone> Default for EventChannel<E> {
+    fn default() -> Self {
+        EventChannel::new()
+    }
+}
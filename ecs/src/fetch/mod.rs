@@ -0,0 +1,147 @@
+use std::any::TypeId;
+use std::any::type_name;
+use std::marker::PhantomData;
+use ECS;
+use component::Component;
+use component::ComponentStore;
+use component::ComponentReadHandle;
+use component::ComponentWriteHandle;
+use resource::Resource;
+use resource::ResourceReadHandle;
+use resource::ResourceWriteHandle;
+use lock::AccessError;
+
+///shared access to the resource T
+pub struct Read<T>(PhantomData<T>);
+///exclusive access to the resource T
+pub struct Write<T>(PhantomData<T>);
+///shared access to the components of type T
+pub struct ReadComp<T>(PhantomData<T>);
+///exclusive access to the components of type T
+pub struct WriteComp<T>(PhantomData<T>);
+
+///a single lock that can be taken as part of a fetch
+pub trait FetchOne<'a> {
+    type Output;
+    ///identifies the lock being taken, fetches acquire locks sorted by this key
+    fn key() -> (TypeId, &'static str);
+    fn fetch_one(ecs: &'a ECS) -> Result<Self::Output, AccessError>;
+}
+
+///a set of locks taken together through ECS::fetch
+pub trait Fetch<'a> {
+    type Output;
+    fn fetch(ecs: &'a ECS) -> Result<Self::Output, AccessError>;
+}
+
+impl<'a, T: 'static + Send + Sync> FetchOne<'a> for Read<T> {
+    type Output = ResourceReadHandle<'a, T>;
+
+    fn key() -> (TypeId, &'static str) {
+        (TypeId::of::<Resource<T>>(), type_name::<T>())
+    }
+
+    #[track_caller]
+    fn fetch_one(ecs: &'a ECS) -> Result<Self::Output, AccessError> {
+        match ecs.get_resource::<T>() {
+            Ok(handle) => Ok(handle),
+            Err(_) => Err(AccessError::Missing(type_name::<T>()))
+        }
+    }
+}
+
+impl<'a, T: 'static + Send + Sync> FetchOne<'a> for Write<T> {
+    type Output = ResourceWriteHandle<'a, T>;
+
+    fn key() -> (TypeId, &'static str) {
+        (TypeId::of::<Resource<T>>(), type_name::<T>())
+    }
+
+    #[track_caller]
+    fn fetch_one(ecs: &'a ECS) -> Result<Self::Output, AccessError> {
+        match ecs.get_mut_resource::<T>() {
+            Ok(handle) => Ok(handle),
+            Err(_) => Err(AccessError::Missing(type_name::<T>()))
+        }
+    }
+}
+
+impl<'a, T: Component> FetchOne<'a> for ReadComp<T> {
+    type Output = ComponentReadHandle<'a, T::ComponentStorage>;
+
+    fn key() -> (TypeId, &'static str) {
+        (TypeId::of::<ComponentStore<T::ComponentStorage>>(), type_name::<T>())
+    }
+
+    #[track_caller]
+    fn fetch_one(ecs: &'a ECS) -> Result<Self::Output, AccessError> {
+        if ecs.archetypes.is_some() {
+            return Err(AccessError::Unsupported(type_name::<T>()));
+        }
+        match ecs.storage.get::<T>() {
+            Ok(store) => Ok(store.read_handle()),
+            Err(_) => Err(AccessError::Missing(type_name::<T>()))
+        }
+    }
+}
+
+impl<'a, T: Component> FetchOne<'a> for WriteComp<T> {
+    type Output = ComponentWriteHandle<'a, T::ComponentStorage>;
+
+    fn key() -> (TypeId, &'static str) {
+        (TypeId::of::<ComponentStore<T::ComponentStorage>>(), type_name::<T>())
+    }
+
+    #[track_caller]
+    fn fetch_one(ecs: &'a ECS) -> Result<Self::Output, AccessError> {
+        if ecs.archetypes.is_some() {
+            return Err(AccessError::Unsupported(type_name::<T>()));
+        }
+        match ecs.storage.get::<T>() {
+            Ok(store) => Ok(store.write_handle()),
+            Err(_) => Err(AccessError::Missing(type_name::<T>()))
+        }
+    }
+}
+
+///positions of the keys in the order their locks must be taken, every fetch uses the same order so overlapping fetches cannot deadlock
+pub fn canonical_order(keys: &[(TypeId, &'static str)]) -> Result<Vec<usize>, AccessError> {
+    let mut order = (0 .. keys.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| keys[*i].0);
+    for pair in order.windows(2) {
+        if keys[pair[0]].0 == keys[pair[1]].0 {
+            return Err(AccessError::Conflict(keys[pair[0]].1));
+        }
+    }
+    Ok(order)
+}
+
+macro_rules! impl_fetch_tuple {
+    ($($name:ident $index:tt),+) => {
+        impl<'a, $($name: FetchOne<'a>),+> Fetch<'a> for ($($name,)+) {
+            type Output = ($($name::Output,)+);
+
+            #[track_caller]
+            fn fetch(ecs: &'a ECS) -> Result<Self::Output, AccessError> {
+                let keys = [$($name::key()),+];
+                let mut slots = ($(None::<$name::Output>,)+);
+                for i in canonical_order(&keys)? {
+                    match i {
+                        $($index => slots.$index = Some($name::fetch_one(ecs)?),)+
+                        _ => unreachable!()
+                    }
+                }
+                Ok(($(slots.$index.expect("lock was not taken"),)+))
+            }
+        }
+    }
+}
+
+impl_fetch_tuple!(A 0);
+impl_fetch_tuple!(A 0, B 1);
+impl_fetch_tuple!(A 0, B 1, C 2);
+impl_fetch_tuple!(A 0, B 1, C 2, D 3);
+impl_fetch_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_fetch_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_fetch_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_fetch_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
@@ -1,16 +1,48 @@
 pub mod component;
 pub mod entity;
 pub mod resource;
+pub mod event;
+pub mod lock;
+pub mod fetch;
+#[cfg(feature = "serde")]
+pub mod serialize;
+#[cfg(feature = "serde")]
+pub mod prefab;
+pub mod snapshot;
+//hashed components are registered on the serialization TypeRegistry, see the serde feature in Cargo.toml
+#[cfg(feature = "serde")]
+pub mod checksum;
+#[cfg(feature = "serde")]
+pub mod delta;
+pub mod net;
+pub mod archetype;
 #[cfg(test)]
 mod tests;
 
 extern crate core;
+extern crate crossbeam;
 #[macro_use]
 extern crate downcast_rs;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "serde")]
+extern crate serde_value;
+#[cfg(feature = "serde")]
+extern crate ron;
+#[cfg(feature = "serde")]
+extern crate serde_cbor;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[macro_use]
+extern crate log;
 use component::ComponentStorage;
 use entity::management::EntityAllocator;
 use entity::EntityIndex;
 use std::any::Any;
+use std::any::TypeId;
 use component::Component;
 use component::ComponentReadHandle;
 use component::ComponentWriteHandle;
@@ -20,12 +52,52 @@ use resource::ResourceWriteHandle;
 use resource::ResourceReadHandle;
 use resource::Resource;
 use resource::ResourceMap;
+use resource::FromWorld;
+use resource::NonSendReadHandle;
+use resource::NonSendWriteHandle;
+use std::thread::ThreadId;
+use event::EventChannel;
+use event::EntityEvent;
+use event::ComponentEvent;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+use std::any::type_name;
+use lock::AccessError;
+use fetch::Fetch;
+#[cfg(feature = "serde")]
+use serialize::TypeRegistry;
+#[cfg(feature = "serde")]
+use serialize::Format;
+#[cfg(feature = "serde")]
+use serialize::SerializeError;
+#[cfg(feature = "serde")]
+use serialize::WorldData;
+use entity::mapping::EntityMap;
+#[cfg(feature = "serde")]
+use prefab::PrefabLibrary;
+use snapshot::WorldSnapshot;
+#[cfg(feature = "serde")]
+use delta::WorldDelta;
+use net::id::NetworkId;
+use net::id::NetworkIndex;
+use component::registry::ComponentRegistry;
+use component::HookContext;
+use archetype::Archetypes;
+use archetype::Layout;
+use entity::management::ReusePolicy;
+#[cfg(feature = "serde")]
+use checksum::ChecksumReport;
+#[cfg(feature = "serde")]
+use prefab::PrefabError;
 
 //generational data structure
 pub struct ECS {
     pub storage: ComponentStorage,
     pub entity_list: EntityAllocator,
     pub resources: ResourceMap,
+    pub entity_events: EventChannel<EntityEvent>,
+    ///component tables used instead of the component stores when the world was built with the archetype layout
+    pub archetypes: Option<Archetypes>,
     pub size: usize
 }
 
@@ -34,15 +106,34 @@ impl<'cs> ECS {
     pub fn allocate_new_entity(&mut self) -> EntityIndex {
         self.size += 1;
         let entity = self.entity_list.allocate();
+        self.entity_events.emit(EntityEvent::Allocated(entity));
         entity
     }
 
+    ///allocate one particular entity, used by clients mirroring a server's entities
+    pub fn allocate_entity_at(&mut self, id: EntityIndex) -> Result<EntityIndex, &str> {
+        let entity = self.entity_list.allocate_at(id)?;
+        self.size += 1;
+        self.entity_events.emit(EntityEvent::Allocated(entity));
+        Ok(entity)
+    }
+
     pub fn deallocate_entity(&mut self, id: EntityIndex) -> Result<(), &str> {
         self.size -= 1;
-        if id.1 == self.entity_list.entity_list[id.0].generation && self.entity_list.entity_list[id.0].is_live{
+        if self.entity_list.is_live(id) {
             let entity = self.entity_list.deallocate(id);
             match entity {
-                Ok(_) => {self.storage.clear_entity(id)},
+                Ok(_) => {
+                    self.entity_events.emit(EntityEvent::Deallocated(id));
+                    if let Some(ref mut archetypes) = self.archetypes {
+                        let mut ctx = HookContext::new();
+                        archetypes.despawn(id, &mut ctx);
+                        self.storage.queue_hook_commands(ctx);
+                    }
+                    let cleared = self.storage.clear_entity(id);
+                    self.apply_hook_commands();
+                    cleared
+                },
                 Err(e) =>  Err(e)
             }
         }else{
@@ -51,13 +142,45 @@ impl<'cs> ECS {
     }
 
     pub fn add_component<T: Component>(&mut self, index: EntityIndex, component: T) -> Result<EntityIndex, &str>{
-        if index.1 == self.entity_list.entity_list[index.0].generation && self.entity_list.entity_list[index.0].is_live {
-            self.storage.add_component(component, index)
+        if self.entity_list.is_live(index) {
+            if let Some(ref mut archetypes) = self.archetypes {
+                let mut ctx = HookContext::new();
+                archetypes.insert(index, component, &mut ctx);
+                self.storage.queue_hook_commands(ctx);
+                self.apply_hook_commands();
+                return Ok(index);
+            }
+            let added = self.storage.add_component(component, index);
+            self.apply_hook_commands();
+            added
         }else{
             Err("incorrect generation")
         }
     }
 
+    ///allocate a new entity holding a copy of every component the source entity has
+    ///a networked source's clone gets a fresh network id, sharing the id would move it off the source
+    pub fn clone_entity(&mut self, source: EntityIndex) -> Result<EntityIndex, &str> {
+        if self.archetypes.is_some() {
+            return Err(archetype::UNSUPPORTED);
+        }
+        if !self.entity_list.is_live(source) {
+            return Err("incorrect generation");
+        }
+        let networked = match self.storage.get::<NetworkId>() {
+            Ok(store) => store.read_handle().get_component(source).is_some(),
+            Err(_) => false
+        };
+        let entity = self.allocate_new_entity();
+        self.storage.clone_entity(source, entity, &[TypeId::of::<NetworkId>()]);
+        if networked {
+            let id = self.get_or_insert_resource_with(NetworkIndex::new).next_id();
+            self.add_component(entity, id).expect("entity was just allocated");
+        }
+        self.apply_hook_commands();
+        Ok(entity)
+    }
+
     pub fn register_new_component<T: Component>(&mut self) -> Result<usize, &str> {
         let mut component_storage: Vec<Option<Box<Any>>> = Vec::with_capacity(self.size);
         for _i in 0 .. self.size {
@@ -66,30 +189,175 @@ impl<'cs> ECS {
         self.storage.register_component::<T>()
     }
 
+    ///allocate an entity and give it every component of the named prefab, including those inherited from its bases
+    ///component names are looked up in the same registry used to save and load the world
+    #[cfg(feature = "serde")]
+    pub fn spawn_prefab(&mut self, registry: &TypeRegistry, prefabs: &PrefabLibrary, name: &str) -> Result<EntityIndex, PrefabError> {
+        let components = prefabs.resolve(name)?;
+        if let Some(missing) = components.keys().find(|component| !registry.has_component(component)) {
+            return Err(PrefabError::UnregisteredComponent(missing.clone()));
+        }
+        registry.register_components(self);
+        let entity = self.allocate_new_entity();
+        for (component, value) in components {
+            let insert = registry.inserter(&component).expect("component was checked above");
+            if let Err(e) = insert(self, entity, value) {
+                self.deallocate_entity(entity).expect("entity was just allocated");
+                return Err(PrefabError::Serialize(e));
+            }
+        }
+        Ok(entity)
+    }
+
     pub fn remove_component<T: Component>(&mut self, index: EntityIndex) -> Result<EntityIndex, &str>{
-        if index.1 != self.entity_list.entity_list[index.0].generation && !self.entity_list.entity_list[index.0].is_live {
+        if !self.entity_list.is_live(index) {
             Err("invalid index")
+        }else if let Some(ref mut archetypes) = self.archetypes {
+            let mut ctx = HookContext::new();
+            let removed = archetypes.remove::<T>(index, &mut ctx);
+            self.storage.queue_hook_commands(ctx);
+            self.apply_hook_commands();
+            removed.map(|_| index).ok_or("entity does not have component")
         }else{
-            self.storage.remove_component::<T>(index)
+            let removed = self.storage.remove_component::<T>(index);
+            self.apply_hook_commands();
+            removed
+        }
+    }
+
+    ///run the commands queued by component on_add and on_remove hooks, including any queued while running them
+    pub fn apply_hook_commands(&mut self) {
+        loop {
+            let commands = self.storage.take_hook_commands();
+            if commands.is_empty() {
+                break;
+            }
+            for command in commands {
+                command(self);
+            }
         }
     }
 
+    ///panics if T is not registered or the world uses the archetype layout, see try_get_component_read_handle
+    #[track_caller]
     pub fn get_component_read_handle<T: 'static + Component>(&self) -> ComponentReadHandle<T::ComponentStorage> {
+        assert!(self.archetypes.is_none(), "component handles are {}", archetype::UNSUPPORTED);
         let res = self.storage.get::<T>().unwrap();
-        let strg = res.0.read().unwrap();
-        ComponentReadHandle{ r: strg }
+        res.read_handle()
     }
 
+    ///panics if T is not registered or the world uses the archetype layout, see try_get_component_write_handle
+    #[track_caller]
     pub fn get_component_write_handle<T: 'static + Component>(&self) -> ComponentWriteHandle<T::ComponentStorage> {
+        assert!(self.archetypes.is_none(), "component handles are {}", archetype::UNSUPPORTED);
         let res = self.storage.get::<T>().unwrap();
-        let strg = res.0.write().unwrap();
-        ComponentWriteHandle{ w: strg }
+        res.write_handle()
+    }
+
+    ///get a read handle without blocking, fails with AccessError::Busy while the component is being written to
+    #[track_caller]
+    pub fn try_get_component_read_handle<T: Component>(&self) -> Result<ComponentReadHandle<T::ComponentStorage>, AccessError> {
+        self.component_read_handle_within::<T>(None)
+    }
+
+    ///get a write handle without blocking, fails with AccessError::Busy while any other handle to the component is held
+    #[track_caller]
+    pub fn try_get_component_write_handle<T: Component>(&self) -> Result<ComponentWriteHandle<T::ComponentStorage>, AccessError> {
+        self.component_write_handle_within::<T>(None)
+    }
+
+    ///wait at most timeout for a read handle
+    #[track_caller]
+    pub fn get_component_read_handle_timeout<T: Component>(&self, timeout: Duration) -> Result<ComponentReadHandle<T::ComponentStorage>, AccessError> {
+        self.component_read_handle_within::<T>(Some(timeout))
+    }
+
+    ///wait at most timeout for a write handle
+    #[track_caller]
+    pub fn get_component_write_handle_timeout<T: Component>(&self, timeout: Duration) -> Result<ComponentWriteHandle<T::ComponentStorage>, AccessError> {
+        self.component_write_handle_within::<T>(Some(timeout))
+    }
+
+    #[track_caller]
+    fn component_read_handle_within<T: Component>(&self, timeout: Option<Duration>) -> Result<ComponentReadHandle<T::ComponentStorage>, AccessError> {
+        if self.archetypes.is_some() {
+            return Err(AccessError::Unsupported(type_name::<T>()));
+        }
+        match self.storage.get::<T>() {
+            Ok(res) => res.read_handle_within(timeout),
+            Err(_) => Err(AccessError::Missing(type_name::<T>()))
+        }
+    }
+
+    #[track_caller]
+    fn component_write_handle_within<T: Component>(&self, timeout: Option<Duration>) -> Result<ComponentWriteHandle<T::ComponentStorage>, AccessError> {
+        if self.archetypes.is_some() {
+            return Err(AccessError::Unsupported(type_name::<T>()));
+        }
+        match self.storage.get::<T>() {
+            Ok(res) => res.write_handle_within(timeout),
+            Err(_) => Err(AccessError::Missing(type_name::<T>()))
+        }
+    }
+
+    ///the store of a component type, for exclusive access without taking its lock
+    pub fn get_mut<T: Component>(&mut self) -> Result<&mut T::ComponentStorage, &str>{
+        if self.archetypes.is_some() {
+            return Err(archetype::UNSUPPORTED);
+        }
+        self.storage.get_mut::<T>().map(|res| res.get_mut_handle())
+    }
+
+    ///the current world tick, systems record this to later query for components changed since they ran
+    pub fn current_tick(&self) -> u64 {
+        self.storage.current_tick()
     }
 
-    pub fn get_mut<T: Component>(&mut self) -> &mut T::ComponentStorage{
-        let res = self.storage.get_mut::<T>().unwrap();
-        let component = res.0.get_mut().unwrap();
-        component
+    ///move the world on to the next tick, usually called once per frame
+    pub fn advance_tick(&self) -> u64 {
+        self.storage.advance_tick()
+    }
+
+    ///run update on every live component of type T
+    pub fn update_components<T: Component>(&self) -> Result<(), &str> {
+        if self.archetypes.is_some() {
+            return Err(archetype::UNSUPPORTED);
+        }
+        self.storage.update_component::<T>()
+    }
+
+    ///run update on every live component of every registered type
+    pub fn update_all(&self) -> Result<(), &str> {
+        if self.archetypes.is_some() {
+            return Err(archetype::UNSUPPORTED);
+        }
+        self.storage.update_all();
+        Ok(())
+    }
+
+    ///run update on every live component, updating each registered type on its own thread
+    pub fn update_all_parallel(&self) -> Result<(), &str> {
+        if self.archetypes.is_some() {
+            return Err(archetype::UNSUPPORTED);
+        }
+        self.storage.update_all_parallel();
+        Ok(())
+    }
+
+    ///receive an event every time an entity is allocated or deallocated
+    pub fn subscribe_entity_events(&self) -> Receiver<EntityEvent> {
+        self.entity_events.subscribe()
+    }
+
+    ///receive an event every time a component of type T is inserted, removed or modified
+    pub fn subscribe_component_events<T: Component>(&self) -> Result<Receiver<ComponentEvent>, &str> {
+        if self.archetypes.is_some() {
+            return Err(archetype::UNSUPPORTED);
+        }
+        match self.storage.get::<T>() {
+            Ok(store) => Ok(store.subscribe()),
+            Err(e) => Err(e)
+        }
     }
 
     pub fn get_entity_iterator_live(&self) -> EntityIteratorLive {
@@ -100,32 +368,376 @@ impl<'cs> ECS {
         self.entity_list.get_iter()
     }
 
-    pub fn get_mut_resource<T: 'static>(&self) -> Result<ResourceWriteHandle<T>, &str>{
+    #[track_caller]
+    pub fn get_mut_resource<T: 'static + Send + Sync>(&self) -> Result<ResourceWriteHandle<T>, &str>{
         match self.resources.get_write_resource::<T>() {
             Ok(x) => Ok(x),
             Err(e) => Err(e)
         }
     }
 
-    pub fn get_resource<T: 'static>(&self) -> Result<ResourceReadHandle<T>, &str>{
+    #[track_caller]
+    pub fn get_resource<T: 'static + Send + Sync>(&self) -> Result<ResourceReadHandle<T>, &str>{
         match self.resources.get_read_resource::<T>() {
             Ok(x) => Ok(x),
             Err(e) => Err(e)
         }
     }
 
-    pub fn remove_resource<T:'static>(&mut self) -> Result<Resource<T>, &str>{
+    ///take a set of resource and component handles at once, for example fetch::<(Read<Time>, WriteComp<Position>)>()
+    ///locks are always taken in the same order so overlapping fetches on different threads cannot deadlock
+    #[track_caller]
+    pub fn fetch<'a, F: Fetch<'a>>(&'a self) -> Result<F::Output, AccessError> {
+        F::fetch(self)
+    }
+
+    ///get a mutable resource handle without blocking
+    #[track_caller]
+    pub fn try_get_mut_resource<T: 'static + Send + Sync>(&self) -> Result<ResourceWriteHandle<T>, AccessError>{
+        self.resources.get_write_resource_within::<T>(None)
+    }
+
+    ///get a resource handle without blocking
+    #[track_caller]
+    pub fn try_get_resource<T: 'static + Send + Sync>(&self) -> Result<ResourceReadHandle<T>, AccessError>{
+        self.resources.get_read_resource_within::<T>(None)
+    }
+
+    ///wait at most timeout for a mutable resource handle
+    #[track_caller]
+    pub fn get_mut_resource_timeout<T: 'static + Send + Sync>(&self, timeout: Duration) -> Result<ResourceWriteHandle<T>, AccessError>{
+        self.resources.get_write_resource_within::<T>(Some(timeout))
+    }
+
+    ///wait at most timeout for a resource handle
+    #[track_caller]
+    pub fn get_resource_timeout<T: 'static + Send + Sync>(&self, timeout: Duration) -> Result<ResourceReadHandle<T>, AccessError>{
+        self.resources.get_read_resource_within::<T>(Some(timeout))
+    }
+
+    pub fn remove_resource<T:'static + Send + Sync>(&mut self) -> Result<Resource<T>, &str>{
         match self.resources.remove_resource::<T>() {
             Err(e) => Err(e),
             Ok(x) => Ok(x)
         }
     }
 
-    pub fn insert_new_resource<T:'static>(&mut self, resource: T){
+    pub fn insert_new_resource<T:'static + Send + Sync>(&mut self, resource: T){
         self.resources.insert_resource::<T>(resource);
     }
 
+    ///insert a resource only if none of the same type is stored yet
+    pub fn try_insert_resource<T:'static + Send + Sync>(&mut self, resource: T) -> Result<(), &str>{
+        self.resources.try_insert_resource::<T>(resource)
+    }
+
+    ///get the stored resource, building and inserting it with f first if it does not exist
+    #[track_caller]
+    pub fn get_or_insert_resource_with<T:'static + Send + Sync, F: FnOnce() -> T>(&mut self, f: F) -> ResourceWriteHandle<T>{
+        if !self.resources.contains_resource::<T>() {
+            self.resources.insert_resource(f());
+        }
+        self.resources.get_write_resource::<T>().expect("resource was just inserted")
+    }
+
+    ///insert a resource built from the current world, or from Default, unless one is already stored
+    pub fn init_resource<T:'static + Send + Sync + FromWorld>(&mut self){
+        if !self.resources.contains_resource::<T>() {
+            let resource = T::from_world(self);
+            self.resources.insert_resource(resource);
+        }
+    }
+
+    ///insert a resource that is not Send or Sync, such as an Rc based cache, it can only be used from the main thread
+    ///it leaks if the ECS is dropped on another thread, remove it on the main thread first
+    pub fn insert_non_send_resource<T:'static>(&mut self, resource: T) -> Result<(), AccessError>{
+        self.resources.insert_non_send_resource(resource)
+    }
+
+    ///get a non send resource, fails with AccessError::WrongThread off the main thread
+    pub fn get_non_send_resource<T:'static>(&self) -> Result<NonSendReadHandle<T>, AccessError>{
+        self.resources.get_non_send_resource::<T>()
+    }
+
+    ///get a mutable non send resource, fails with AccessError::WrongThread off the main thread
+    pub fn get_non_send_resource_mut<T:'static>(&self) -> Result<NonSendWriteHandle<T>, AccessError>{
+        self.resources.get_non_send_resource_mut::<T>()
+    }
+
+    ///take a non send resource out of the ECS, fails with AccessError::WrongThread off the main thread
+    pub fn remove_non_send_resource<T:'static>(&mut self) -> Result<T, AccessError>{
+        self.resources.remove_non_send_resource::<T>()
+    }
+
+    ///the thread the ECS was created on, systems using non send resources must be run here
+    ///the ECS does not schedule systems, so keeping them on this thread is up to the caller
+    pub fn main_thread(&self) -> ThreadId {
+        self.resources.main_thread()
+    }
+
+    pub fn is_main_thread(&self) -> bool {
+        self.resources.is_main_thread()
+    }
+
+    ///the changes since a previously saved state, in terms of the components registered with registry
+    #[cfg(feature = "serde")]
+    pub fn delta_since(&self, registry: &TypeRegistry, previous: &WorldData) -> Result<WorldDelta, SerializeError> {
+        Ok(WorldDelta::between(previous, &registry.save(self)?))
+    }
+
+    ///apply changes produced by delta_since on another ECS
+    #[cfg(feature = "serde")]
+    pub fn apply_delta(&mut self, registry: &TypeRegistry, delta: &WorldDelta) -> Result<(), SerializeError> {
+        delta.apply(registry, self)
+    }
+
+    ///allocate an entity with a fresh network id, the NetworkId component is registered if it was not already
+    pub fn spawn_networked(&mut self) -> (EntityIndex, NetworkId) {
+        if self.storage.get::<NetworkId>().is_err() {
+            self.register_new_component::<NetworkId>().expect("component was not registered");
+        }
+        let id = self.get_or_insert_resource_with(NetworkIndex::new).next_id();
+        let entity = self.allocate_new_entity();
+        self.add_component(entity, id).expect("entity was just allocated");
+        (entity, id)
+    }
+
+    ///rebuild the network id index from the NetworkId components, used after loading or restoring a world
+    pub fn rebuild_network_index(&mut self) {
+        if self.storage.get::<NetworkId>().is_ok() {
+            let mut index = NetworkIndex::new();
+            index.rebuild(self);
+            self.insert_new_resource(index);
+        }
+    }
+
+    ///the local entity carrying a network id
+    pub fn entity_for_network_id(&self, id: NetworkId) -> Option<EntityIndex> {
+        match self.get_resource::<NetworkIndex>() {
+            Ok(index) => index.entity(id),
+            Err(_) => None
+        }
+    }
+
+    ///the network id of a local entity
+    pub fn network_id(&self, entity: EntityIndex) -> Option<NetworkId> {
+        match self.get_resource::<NetworkIndex>() {
+            Ok(index) => index.id(entity),
+            Err(_) => None
+        }
+    }
+
+    ///deterministic hash of every live entity and every component hashed by the registry, see TypeRegistry::register_hashed_component
+    #[cfg(feature = "serde")]
+    pub fn checksum(&self, registry: &TypeRegistry) -> Result<u64, SerializeError> {
+        registry.checksum_report(self).map(|report| report.total())
+    }
+
+    ///the hashes checksum is built from, compare reports from two peers with first_divergence to find the component type that desynced
+    #[cfg(feature = "serde")]
+    pub fn checksum_report(&self, registry: &TypeRegistry) -> Result<ChecksumReport, SerializeError> {
+        registry.checksum_report(self)
+    }
+
+    ///cheap in memory copy of the world for rollback, component stores unchanged since the last snapshot are shared rather than copied
+    pub fn snapshot(&self) -> Result<WorldSnapshot, &'static str> {
+        WorldSnapshot::capture(self)
+    }
+
+    ///put the world back to a snapshot, no hooks run and no entity or component events are emitted
+    ///anything mirroring the world through events has to resync afterwards
+    pub fn restore(&mut self, snapshot: &WorldSnapshot) -> Result<(), &'static str> {
+        snapshot.apply(self)
+    }
+
+    ///include resources of this type in snapshots, other resources are left alone by restore
+    pub fn register_rollback_resource<T:'static + Send + Sync + Clone>(&mut self) {
+        self.resources.register_rollback::<T>()
+    }
+
+    ///save the entity allocator, every registered component and every registered resource in the given format
+    ///the allocator is saved with its reuse policy and generation limit
+    #[cfg(feature = "serde")]
+    pub fn save<F: Format>(&self, registry: &TypeRegistry, format: &F) -> Result<Vec<u8>, SerializeError> {
+        format.encode(&registry.save(self)?)
+    }
+
+    ///build a new ECS from bytes written by save
+    ///the world keeps the reuse policy it was saved with, call set_reuse_policy on the result to hand out indices another way
+    #[cfg(feature = "serde")]
+    pub fn load<F: Format>(registry: &TypeRegistry, format: &F, bytes: &[u8]) -> Result<ECS, SerializeError> {
+        registry.load(format.decode(bytes)?)
+    }
+
+    ///add the entities in bytes written by save to this ECS as new entities, returning where each one ended up
+    ///they are allocated by this ECS's reuse policy, the saved policy is ignored
+    #[cfg(feature = "serde")]
+    pub fn merge<F: Format>(&mut self, registry: &TypeRegistry, format: &F, bytes: &[u8]) -> Result<EntityMap, SerializeError> {
+        let data = format.decode(bytes)?;
+        registry.merge(self, data)
+    }
+
+    ///copy every live entity of another ECS into this one as new entities, returning where each one ended up
+    #[cfg(feature = "serde")]
+    pub fn merge_world(&mut self, registry: &TypeRegistry, other: &ECS) -> Result<EntityMap, SerializeError> {
+        let data = registry.save(other)?;
+        registry.merge(self, data)
+    }
+
+    ///trim the entity allocator and every component store down to the last live entity and release unused capacity
+    ///entity indices are left as they are, see compact to also close the gaps between them
+    pub fn maintain(&mut self) {
+        self.entity_list.trim();
+        self.storage.trim();
+        if let Some(ref mut archetypes) = self.archetypes {
+            archetypes.trim();
+        }
+    }
+
+    ///renumber the live entities into the lowest indices, moving their components along, then trim like maintain
+    ///every entity gets a new generation, references held by components registered with TypeRegistry::register_mapped_component are rewritten
+    ///and an EntityEvent::Remapped is emitted per entity, handles held anywhere else must be rewritten with the returned map
+    #[cfg(feature = "serde")]
+    pub fn compact(&mut self, registry: &TypeRegistry) -> EntityMap {
+        let map = self.compact_entities();
+        registry.remap(self, &map);
+        map
+    }
+
+    ///renumber the live entities into the lowest indices, moving their components along, then trim like maintain
+    ///every entity gets a new generation and an EntityEvent::Remapped is emitted per entity
+    ///without the serde feature there is no registry of components holding entities, rewrite them with the returned map
+    #[cfg(not(feature = "serde"))]
+    pub fn compact(&mut self) -> EntityMap {
+        self.compact_entities()
+    }
+
+    fn compact_entities(&mut self) -> EntityMap {
+        let map = self.entity_list.compact();
+        let mut moves = map.iter().map(|(old, new)| (old.index(), *new)).collect::<Vec<_>>();
+        //entities keep their order, so moving the lowest first always lands in a slot that has been emptied already
+        //entities that stay put still move, so their stores record the new generation
+        moves.sort();
+        for (from, to) in moves {
+            self.storage.move_slot(from, to);
+        }
+        if let Some(ref mut archetypes) = self.archetypes {
+            archetypes.remap(&map);
+        }
+        self.maintain();
+        if self.resources.contains_resource::<NetworkIndex>() {
+            self.rebuild_network_index();
+        }
+        let mut remapped = map.iter().filter(|(old, new)| old != new).map(|(old, new)| (*old, *new)).collect::<Vec<_>>();
+        remapped.sort_by_key(|(_, new)| new.index());
+        for (old, new) in remapped {
+            self.entity_events.emit(EntityEvent::Remapped(old, new));
+        }
+        map
+    }
+
+    ///approximate bytes allocated for entities and components in component stores
+    pub fn heap_size(&self) -> usize {
+        self.entity_list.heap_size() + self.storage.heap_size()
+    }
+
+    ///an empty world storing its components in the given layout
+    pub fn with_layout(layout: Layout) -> ECS {
+        let mut ecs = ECS::new();
+        if layout == Layout::Archetype {
+            ecs.archetypes = Some(Archetypes::new());
+        }
+        ecs
+    }
+
+    pub fn layout(&self) -> Layout {
+        match self.archetypes {
+            Some(_) => Layout::Archetype,
+            None => Layout::Dense
+        }
+    }
+
+    ///an empty world handing out freed entity indices by the given policy
+    pub fn with_policy(policy: ReusePolicy) -> ECS {
+        let mut ecs = ECS::new();
+        ecs.entity_list.set_policy(policy);
+        ecs
+    }
+
+    ///change which freed entity index is handed out next, indices already free are kept
+    pub fn set_reuse_policy(&mut self, policy: ReusePolicy) {
+        self.entity_list.set_policy(policy);
+    }
+
+    pub fn reuse_policy(&self) -> ReusePolicy {
+        self.entity_list.policy
+    }
+
+    ///an empty world sharing its component types with every other world using the registry
+    pub fn with_registry(registry: &ComponentRegistry) -> ECS {
+        let mut ecs = ECS::new();
+        ecs.storage = ComponentStorage::with_registry(registry.clone());
+        ecs
+    }
+
+    ///the registry shared with the other worlds built from it, a world made with new has one of its own
+    pub fn component_registry(&self) -> &ComponentRegistry {
+        self.storage.registry()
+    }
+
+    ///create stores for component types registered through other worlds since this one was built
+    pub fn sync_components(&mut self) {
+        let registry = self.storage.registry().clone();
+        self.storage.install(&registry);
+    }
+
+    ///move an entity and all of its components from one world to another, returning its index in the destination
+    ///on_remove hooks run in the source and on_add hooks in the destination, which gains stores for any types it is missing
+    ///if the destination refuses a component the move is undone, the entity keeps its index and every other component in
+    ///the source, with their hooks run again, and only the refused component is lost
+    pub fn move_entity(src: &mut ECS, dst: &mut ECS, entity: EntityIndex) -> Result<EntityIndex, &'static str> {
+        if src.archetypes.is_some() || dst.archetypes.is_some() {
+            return Err(archetype::UNSUPPORTED);
+        }
+        if !src.entity_list.is_live(entity) {
+            return Err("incorrect generation");
+        }
+        dst.storage.install(src.storage.registry());
+        let moved = dst.allocate_new_entity();
+        let mut components = src.storage.take_entity(entity);
+        if !dst.storage.has_stores(components.iter().map(|(ty, _)| ty)) {
+            for (ty, component) in components {
+                src.storage.insert_boxed(ty, entity, component).expect("unable to restore a component to its own store");
+            }
+            src.apply_hook_commands();
+            dst.deallocate_entity(moved).expect("unable to deallocate a fresh entity");
+            return Err("unregistered type");
+        }
+        let mut pending = components.into_iter();
+        let mut failure = None;
+        while let Some((ty, component)) = pending.next() {
+            if let Err((e, refused)) = dst.storage.insert_boxed(ty, moved, component) {
+                failure = Some((e, (ty, refused)));
+                break;
+            }
+        }
+        if let Some((e, refused)) = failure {
+            //hand back everything the destination took, the component it refused and what was never tried
+            let returned = dst.storage.take_entity(moved);
+            for (ty, component) in returned.into_iter().chain(Some(refused)).chain(pending) {
+                src.storage.insert_boxed(ty, entity, component).expect("unable to restore a component to its own store");
+            }
+            dst.deallocate_entity(moved).expect("unable to deallocate a fresh entity");
+            dst.apply_hook_commands();
+            src.apply_hook_commands();
+            return Err(e);
+        }
+        src.deallocate_entity(entity).map_err(|_| "unable to deallocate entity")?;
+        dst.apply_hook_commands();
+        Ok(moved)
+    }
+
     pub fn new() -> ECS {
-        ECS {storage: ComponentStorage::new(), entity_list: EntityAllocator::new(), resources: ResourceMap::default(), size: 0}
+        ECS {storage: ComponentStorage::new(), entity_list: EntityAllocator::new(), resources: ResourceMap::default(), entity_events: EventChannel::new(), archetypes: None, size: 0}
     }
 }
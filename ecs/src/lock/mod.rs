@@ -0,0 +1,346 @@
+use std::fmt;
+use std::thread;
+use std::thread::ThreadId;
+use std::time::Duration;
+use std::time::Instant;
+#[cfg(debug_assertions)]
+use std::sync::Mutex;
+#[cfg(debug_assertions)]
+use std::sync::OnceLock;
+#[cfg(debug_assertions)]
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
+use std::sync::TryLockError;
+#[cfg(debug_assertions)]
+use std::sync::mpsc;
+#[cfg(debug_assertions)]
+use std::sync::atomic::AtomicU64;
+#[cfg(debug_assertions)]
+use std::sync::atomic::Ordering;
+use std::panic::Location;
+
+///how long a debug build waits on a blocking acquisition before logging who holds the lock
+pub const DEADLOCK_REPORT_AFTER: Duration = Duration::from_secs(5);
+
+//timed acquisitions sleep between attempts, starting short and doubling up to the cap
+const FIRST_BACKOFF: Duration = Duration::from_micros(50);
+const MAX_BACKOFF: Duration = Duration::from_millis(10);
+
+///reason a component store or resource could not be handed out
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccessError {
+    ///the lock is held elsewhere and did not become free in time, debug builds list who holds it
+    Busy(&'static str, Vec<Borrow>),
+    ///nothing of this type has been registered
+    Missing(&'static str),
+    ///the same lock was requested more than once in a single fetch
+    Conflict(&'static str),
+    ///a non send resource was used from a thread other than the main thread
+    WrongThread(&'static str),
+    ///the world's layout keeps this type out of component stores
+    Unsupported(&'static str)
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccessError::Busy(name, holders) => {
+                write!(f, "{} is busy", name)?;
+                for holder in holders {
+                    write!(f, ", {} {}", name, holder)?;
+                }
+                Ok(())
+            },
+            AccessError::Missing(name) => write!(f, "{} is not registered", name),
+            AccessError::Conflict(name) => write!(f, "{} was requested more than once", name),
+            AccessError::WrongThread(name) => write!(f, "{} may only be used from the main thread", name),
+            AccessError::Unsupported(name) => write!(f, "handles to {} are not supported by the archetype layout", name)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write
+}
+
+///a lock acquisition recorded by a BorrowTracker
+#[derive(Clone, Debug, PartialEq)]
+pub struct Borrow {
+    pub kind: AccessKind,
+    pub thread: ThreadId,
+    pub thread_name: Option<String>,
+    pub location: &'static Location<'static>
+}
+
+impl fmt::Display for Borrow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            AccessKind::Read => "read-locked",
+            AccessKind::Write => "write-locked"
+        };
+        write!(f, "{} at {} by thread ", kind, self.location)?;
+        match self.thread_name {
+            Some(ref name) => write!(f, "{} ({:?})", name, self.thread),
+            None => write!(f, "{:?}", self.thread)
+        }
+    }
+}
+
+///records which thread and call site hold a lock, does nothing in release builds
+pub struct BorrowTracker {
+    #[cfg(debug_assertions)]
+    borrows: Mutex<Vec<(u64, Borrow)>>,
+    #[cfg(debug_assertions)]
+    next: AtomicU64
+}
+
+impl BorrowTracker {
+
+    #[cfg(debug_assertions)]
+    pub fn new() -> BorrowTracker {
+        BorrowTracker{ borrows: Mutex::new(Vec::new()), next: AtomicU64::new(0) }
+    }
+
+    #[cfg(not(debug_assertions))]
+    pub fn new() -> BorrowTracker {
+        BorrowTracker{}
+    }
+
+    ///every acquisition currently held
+    #[cfg(debug_assertions)]
+    pub fn holders(&self) -> Vec<Borrow> {
+        self.lock_borrows().iter().map(|b| b.1.clone()).collect()
+    }
+
+    #[cfg(not(debug_assertions))]
+    pub fn holders(&self) -> Vec<Borrow> {
+        Vec::new()
+    }
+
+    ///describe every current holder of the lock, one per line
+    pub fn report(&self, name: &str) -> String {
+        self.holders().iter().map(|b| format!("{} {}", name, b)).collect::<Vec<_>>().join("\n")
+    }
+
+    #[cfg(debug_assertions)]
+    fn acquire(&self, kind: AccessKind, location: &'static Location<'static>) -> BorrowToken {
+        let id = self.next.fetch_add(1, Ordering::SeqCst);
+        let current = thread::current();
+        let borrow = Borrow{ kind, thread: current.id(), thread_name: current.name().map(String::from), location };
+        self.lock_borrows().push((id, borrow));
+        BorrowToken{ tracker: self, id }
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn acquire(&self, _kind: AccessKind, _location: &'static Location<'static>) -> BorrowToken {
+        BorrowToken{ tracker: self, id: 0 }
+    }
+
+    //a thread blocking on a lock it already holds can never wake up
+    #[cfg(debug_assertions)]
+    fn check(&self, kind: AccessKind, name: &str, location: &'static Location<'static>) {
+        let current = thread::current().id();
+        let conflict = self.lock_borrows().iter()
+            .any(|b| b.1.thread == current && (kind == AccessKind::Write || b.1.kind == AccessKind::Write));
+        if conflict {
+            panic!("deadlock: {} requested at {} while this thread already holds it\n{}", name, location, self.report(name));
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn check(&self, _kind: AccessKind, _name: &str, _location: &'static Location<'static>) {}
+
+    #[cfg(debug_assertions)]
+    fn release(&self, id: u64) {
+        self.lock_borrows().retain(|b| b.0 != id);
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn release(&self, _id: u64) {}
+
+    #[cfg(debug_assertions)]
+    fn lock_borrows(&self) -> ::std::sync::MutexGuard<Vec<(u64, Borrow)>> {
+        self.borrows.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for BorrowTracker {
+    fn default() -> Self {
+        BorrowTracker::new()
+    }
+}
+
+///kept alongside a lock guard, removes the recorded acquisition when dropped
+pub struct BorrowToken<'l> {
+    tracker: &'l BorrowTracker,
+    id: u64
+}
+
+impl<'l> Drop for BorrowToken<'l> {
+    fn drop(&mut self) {
+        self.tracker.release(self.id);
+    }
+}
+
+//a poisoned lock only means another thread panicked while holding it, the data itself is still usable
+///block until the read lock is acquired, recovering the guard if the lock is poisoned
+#[track_caller]
+pub fn read<'l, T>(lock: &'l RwLock<T>, tracker: &'l BorrowTracker, name: &'static str) -> (RwLockReadGuard<'l, T>, BorrowToken<'l>) {
+    let location = Location::caller();
+    tracker.check(AccessKind::Read, name, location);
+    let guard = match lock.try_read() {
+        Ok(guard) => guard,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => {
+            let _watchdog = watch(tracker, name);
+            lock.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    };
+    (guard, tracker.acquire(AccessKind::Read, location))
+}
+
+///block until the write lock is acquired, recovering the guard if the lock is poisoned
+#[track_caller]
+pub fn write<'l, T>(lock: &'l RwLock<T>, tracker: &'l BorrowTracker, name: &'static str) -> (RwLockWriteGuard<'l, T>, BorrowToken<'l>) {
+    let location = Location::caller();
+    tracker.check(AccessKind::Write, name, location);
+    let guard = match lock.try_write() {
+        Ok(guard) => guard,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => {
+            let _watchdog = watch(tracker, name);
+            lock.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    };
+    (guard, tracker.acquire(AccessKind::Write, location))
+}
+
+///get the value behind a lock we hold exclusively, recovering it if the lock is poisoned
+pub fn get_mut<T>(lock: &mut RwLock<T>) -> &mut T {
+    lock.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+///try to take the read lock, retrying until the timeout runs out, None tries exactly once
+#[track_caller]
+pub fn read_within<'l, T>(lock: &'l RwLock<T>, tracker: &'l BorrowTracker, timeout: Option<Duration>, name: &'static str) -> Result<(RwLockReadGuard<'l, T>, BorrowToken<'l>), AccessError> {
+    let location = Location::caller();
+    let start = Instant::now();
+    let mut backoff = FIRST_BACKOFF;
+    loop {
+        match lock.try_read() {
+            Ok(guard) => return Ok((guard, tracker.acquire(AccessKind::Read, location))),
+            Err(TryLockError::Poisoned(poisoned)) => return Ok((poisoned.into_inner(), tracker.acquire(AccessKind::Read, location))),
+            Err(TryLockError::WouldBlock) => if !wait(start, timeout, &mut backoff) {
+                return Err(AccessError::Busy(name, tracker.holders()))
+            }
+        }
+    }
+}
+
+///try to take the write lock, retrying until the timeout runs out, None tries exactly once
+#[track_caller]
+pub fn write_within<'l, T>(lock: &'l RwLock<T>, tracker: &'l BorrowTracker, timeout: Option<Duration>, name: &'static str) -> Result<(RwLockWriteGuard<'l, T>, BorrowToken<'l>), AccessError> {
+    let location = Location::caller();
+    let start = Instant::now();
+    let mut backoff = FIRST_BACKOFF;
+    loop {
+        match lock.try_write() {
+            Ok(guard) => return Ok((guard, tracker.acquire(AccessKind::Write, location))),
+            Err(TryLockError::Poisoned(poisoned)) => return Ok((poisoned.into_inner(), tracker.acquire(AccessKind::Write, location))),
+            Err(TryLockError::WouldBlock) => if !wait(start, timeout, &mut backoff) {
+                return Err(AccessError::Busy(name, tracker.holders()))
+            }
+        }
+    }
+}
+
+//sleeps for the backoff, never past the timeout, and reports whether it is worth trying again
+fn wait(start: Instant, timeout: Option<Duration>, backoff: &mut Duration) -> bool {
+    match timeout {
+        Some(timeout) if start.elapsed() < timeout => {
+            thread::sleep((*backoff).min(timeout.saturating_sub(start.elapsed())));
+            *backoff = (*backoff * 2).min(MAX_BACKOFF);
+            true
+        },
+        _ => false
+    }
+}
+
+//sent to the watchdog thread when a blocked acquisition starts and when it goes through
+#[cfg(debug_assertions)]
+enum Watch {
+    Start(u64, Instant, AccessError),
+    Stop(u64)
+}
+
+//stops watching its acquisition when dropped, which is once the blocked acquisition goes through
+struct Watchdog {
+    #[cfg(debug_assertions)]
+    watch: Option<(mpsc::Sender<Watch>, u64)>
+}
+
+#[cfg(debug_assertions)]
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        if let Some((ref watchdog, id)) = self.watch {
+            let _ = watchdog.send(Watch::Stop(id));
+        }
+    }
+}
+
+//debug builds hand an acquisition that has to block to the watchdog thread, which logs a warning naming the holders at the time
+//if the lock is still not free after DEADLOCK_REPORT_AFTER, release builds block without one
+#[cfg(debug_assertions)]
+fn watch(tracker: &BorrowTracker, name: &'static str) -> Watchdog {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let watch = watchdog().and_then(|watchdog| {
+        let id = NEXT.fetch_add(1, Ordering::SeqCst);
+        let busy = AccessError::Busy(name, tracker.holders());
+        watchdog.send(Watch::Start(id, Instant::now() + DEADLOCK_REPORT_AFTER, busy)).ok().map(|_| (watchdog, id))
+    });
+    Watchdog{ watch }
+}
+
+#[cfg(not(debug_assertions))]
+fn watch(_tracker: &BorrowTracker, _name: &'static str) -> Watchdog {
+    Watchdog{}
+}
+
+//a single thread watches every blocked acquisition, started the first time one blocks
+#[cfg(debug_assertions)]
+fn watchdog() -> Option<mpsc::Sender<Watch>> {
+    static WATCHDOG: OnceLock<Option<mpsc::Sender<Watch>>> = OnceLock::new();
+    WATCHDOG.get_or_init(|| {
+        let (watchdog, requests) = mpsc::channel();
+        thread::Builder::new().name(String::from("ecs lock watchdog")).spawn(move || run_watchdog(requests)).ok().map(|_| watchdog)
+    }).clone()
+}
+
+#[cfg(debug_assertions)]
+fn run_watchdog(requests: mpsc::Receiver<Watch>) {
+    let mut blocked: HashMap<u64, (Instant, AccessError)> = HashMap::new();
+    loop {
+        let request = match blocked.values().map(|b| b.0).min() {
+            Some(deadline) => requests.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => requests.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected)
+        };
+        match request {
+            Ok(Watch::Start(id, deadline, busy)) => { blocked.insert(id, (deadline, busy)); },
+            Ok(Watch::Stop(id)) => { blocked.remove(&id); },
+            Err(mpsc::RecvTimeoutError::Timeout) => {},
+            Err(mpsc::RecvTimeoutError::Disconnected) => return
+        }
+        let now = Instant::now();
+        blocked.retain(|_, (deadline, busy)| {
+            if *deadline > now {
+                return true;
+            }
+            warn!("possible deadlock: waited {:?}, {}", DEADLOCK_REPORT_AFTER, busy);
+            false
+        });
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use ECS;
+use component::Iter;
+use component::ComponentRef;
+use component::Component;
+use component::DenseComponentStorage;
+use component::HookContext;
+use entity::EntityIndex;
+
+///identity of an entity that is the same on every machine, unlike an EntityIndex whose generation is local
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NetworkId(pub u64);
+
+impl Component for NetworkId {
+    type ComponentStorage = DenseComponentStorage<Self>;
+
+    fn on_add(&mut self, entity: EntityIndex, ctx: &mut HookContext) {
+        let id = *self;
+        ctx.defer(move |ecs| ecs.get_or_insert_resource_with(NetworkIndex::new).insert(id, entity));
+    }
+
+    fn on_remove(&mut self, entity: EntityIndex, ctx: &mut HookContext) {
+        let id = *self;
+        ctx.defer(move |ecs| ecs.get_or_insert_resource_with(NetworkIndex::new).remove(id, entity));
+    }
+}
+
+///resource mapping network ids to local entities and back, kept up to date by the NetworkId component hooks
+#[derive(Clone, Debug, Default)]
+pub struct NetworkIndex {
+    entities: HashMap<NetworkId, EntityIndex>,
+    ids: HashMap<EntityIndex, NetworkId>,
+    next: u64
+}
+
+impl NetworkIndex {
+
+    pub fn new() -> NetworkIndex {
+        NetworkIndex{ entities: HashMap::new(), ids: HashMap::new(), next: 1 }
+    }
+
+    ///a network id that has not been handed out by this index, only the authoritative side should hand out ids
+    pub fn next_id(&mut self) -> NetworkId {
+        while self.entities.contains_key(&NetworkId(self.next)) {
+            self.next += 1;
+        }
+        self.next += 1;
+        NetworkId(self.next - 1)
+    }
+
+    pub fn entity(&self, id: NetworkId) -> Option<EntityIndex> {
+        self.entities.get(&id).cloned()
+    }
+
+    pub fn id(&self, entity: EntityIndex) -> Option<NetworkId> {
+        self.ids.get(&entity).cloned()
+    }
+
+    ///index every NetworkId component in the ECS, for worlds restored without running component hooks
+    pub fn rebuild(&mut self, ecs: &ECS) {
+        self.entities.clear();
+        self.ids.clear();
+        if let Ok(store) = ecs.storage.get::<NetworkId>() {
+            let handle = store.read_handle();
+            let mut iter = handle.get_iterator();
+            while let Some((id, index)) = iter.next_element(None) {
+                if let Some(entity) = ecs.entity_list.entity(index) {
+                    self.next = self.next.max(id.0 + 1);
+                    self.insert(*id.component(), entity);
+                }
+            }
+        }
+        if let Some(ref archetypes) = ecs.archetypes {
+            archetypes.for_each(|entity, id: &NetworkId| {
+                self.next = self.next.max(id.0 + 1);
+                self.insert(*id, entity);
+            });
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    //an id moved onto another entity drops the old entity's mapping with it
+    fn insert(&mut self, id: NetworkId, entity: EntityIndex) {
+        if let Some(old) = self.entities.insert(id, entity) {
+            self.ids.remove(&old);
+        }
+        if let Some(old) = self.ids.insert(entity, id) {
+            if old != id {
+                self.entities.remove(&old);
+            }
+        }
+    }
+
+    fn remove(&mut self, id: NetworkId, entity: EntityIndex) {
+        if self.entities.get(&id) == Some(&entity) {
+            self.entities.remove(&id);
+        }
+        if self.ids.get(&entity) == Some(&id) {
+            self.ids.remove(&entity);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::io;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
+use std::net::UdpSocket;
+use std::time::Duration;
+use std::time::Instant;
+
+///largest datagram read off the socket
+pub const MAX_PACKET: usize = 65507;
+
+///simulates a bad connection by dropping and delaying outgoing packets, the seed makes the losses repeatable
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkConditioner {
+    ///chance between 0 and 1 that a packet is dropped
+    pub loss: f32,
+    ///how long each packet is held back before it is sent
+    pub latency: Duration,
+    pub seed: u64
+}
+
+impl LinkConditioner {
+
+    pub fn new(loss: f32, latency: Duration, seed: u64) -> LinkConditioner {
+        LinkConditioner{ loss, latency, seed }
+    }
+
+    ///no loss and no added latency
+    pub fn perfect() -> LinkConditioner {
+        LinkConditioner::new(0.0, Duration::from_millis(0), 1)
+    }
+}
+
+impl Default for LinkConditioner {
+    fn default() -> Self {
+        LinkConditioner::perfect()
+    }
+}
+
+///non blocking UDP socket that passes outgoing packets through a link conditioner
+pub struct Link {
+    socket: UdpSocket,
+    conditioner: LinkConditioner,
+    rng: u64,
+    delayed: Vec<(Instant, SocketAddr, Vec<u8>)>
+}
+
+impl Link {
+
+    pub fn bind<A: ToSocketAddrs>(addr: A, conditioner: LinkConditioner) -> io::Result<Link> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Link{ socket, conditioner, rng: conditioner.seed.max(1), delayed: Vec::new() })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    ///queue a packet, it is sent by flush once the simulated latency has passed unless the conditioner drops it
+    pub fn send(&mut self, to: SocketAddr, bytes: Vec<u8>) -> io::Result<()> {
+        if self.conditioner.loss > 0.0 && self.roll() < self.conditioner.loss {
+            return Ok(());
+        }
+        self.delayed.push((Instant::now() + self.conditioner.latency, to, bytes));
+        self.flush()
+    }
+
+    ///send every queued packet whose latency has passed
+    pub fn flush(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let mut i = 0;
+        while i < self.delayed.len() {
+            if self.delayed[i].0 <= now {
+                let (_, to, bytes) = self.delayed.remove(i);
+                self.socket.send_to(&bytes, to)?;
+            }else{
+                i += 1;
+            }
+        }
+        Ok(())
+    }
+
+    ///the next waiting packet, None once the socket is drained
+    pub fn recv(&mut self) -> io::Result<Option<(Vec<u8>, SocketAddr)>> {
+        let mut buffer = vec![0; MAX_PACKET];
+        match self.socket.recv_from(&mut buffer) {
+            Ok((len, from)) => {
+                buffer.truncate(len);
+                Ok(Some((buffer, from)))
+            },
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            //windows reports an unreachable peer on the next receive, that is the same as a lost packet
+            Err(ref e) if e.kind() == ErrorKind::ConnectionReset => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    //xorshift, good enough to decide which packets to drop
+    fn roll(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
@@ -0,0 +1,8 @@
+pub mod link;
+pub mod id;
+//the server and client send worlds as saved data, so they need serialization
+#[cfg(feature = "serde")]
+pub mod replication;
+
+#[cfg(feature = "serde")]
+pub use self::replication::*;
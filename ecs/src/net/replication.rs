@@ -0,0 +1,284 @@
+use std::fmt;
+use std::io;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
+use ECS;
+use entity::EntityIndex;
+use entity::mapping::EntityMap;
+use delta::WorldDelta;
+use serialize::Cbor;
+use serialize::Format;
+use serialize::SerializeError;
+use serialize::TypeRegistry;
+use serialize::WorldData;
+use net::link::Link;
+use net::link::LinkConditioner;
+use net::link::MAX_PACKET;
+
+///reason a server or client update failed
+#[derive(Debug)]
+pub enum NetError {
+    Io(io::Error),
+    Serialize(SerializeError),
+    ///an encoded packet of this many bytes does not fit in one datagram
+    TooLarge(usize),
+    ///these clients could not be sent their state this tick, every other client was
+    Clients(Vec<(SocketAddr, NetError)>)
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetError::Io(e) => write!(f, "socket error: {}", e),
+            NetError::Serialize(e) => write!(f, "{}", e),
+            NetError::TooLarge(size) => write!(f, "packet of {} bytes is larger than the {} a datagram holds", size, MAX_PACKET),
+            NetError::Clients(failed) => {
+                write!(f, "unable to update {} clients", failed.len())?;
+                for (addr, e) in failed {
+                    write!(f, ", {}: {}", addr, e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<io::Error> for NetError {
+    fn from(e: io::Error) -> Self {
+        NetError::Io(e)
+    }
+}
+
+impl From<SerializeError> for NetError {
+    fn from(e: SerializeError) -> Self {
+        NetError::Serialize(e)
+    }
+}
+
+///unacknowledged states kept per client, once a client falls this far behind the oldest are forgotten
+pub const MAX_IN_FLIGHT: usize = 64;
+
+///server ticks a client may go without acknowledging anything before it is disconnected
+pub const DEFAULT_TIMEOUT: Sequence = 300;
+
+///client updates without a new state after which the client asks to be connected again, the server may have dropped it
+pub const RECONNECT_AFTER: Sequence = 60;
+
+///sequence number of a state sent by the server, 0 stands for the empty world every client starts from
+pub type Sequence = u64;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Packet {
+    ///sent by a client until it receives its first state, and again once states stop arriving
+    Connect,
+    ///the server's world as of seq, as changes from the state numbered baseline
+    State{ seq: Sequence, baseline: Sequence, delta: WorldDelta },
+    ///the client has applied the state numbered seq, the server may use it as a baseline
+    Ack{ seq: Sequence }
+}
+
+///decides which entities a client is sent, entities outside its interest are despawned on the client
+pub type Interest = Box<Fn(&ECS, EntityIndex) -> bool + Send>;
+
+struct Connection {
+    acked: Sequence,
+    sent: BTreeMap<Sequence, WorldData>,
+    interest: Option<Interest>,
+    //the server tick the client was last heard from
+    heard: Sequence,
+    //the last state was too large to send, already reported so it is not reported again every tick
+    oversized: bool
+}
+
+///the authoritative side, sends every connected client the components registered with its registry
+pub struct Server {
+    link: Link,
+    registry: TypeRegistry,
+    clients: HashMap<SocketAddr, Connection>,
+    seq: Sequence,
+    timeout: Sequence
+}
+
+impl Server {
+
+    ///listen on addr, only components registered with registry are replicated
+    pub fn bind<A: ToSocketAddrs>(addr: A, registry: TypeRegistry, conditioner: LinkConditioner) -> io::Result<Server> {
+        Ok(Server{ link: Link::bind(addr, conditioner)?, registry, clients: HashMap::new(), seq: 0, timeout: DEFAULT_TIMEOUT })
+    }
+
+    ///disconnect clients that acknowledge nothing for this many ticks, DEFAULT_TIMEOUT unless set
+    pub fn set_timeout(&mut self, ticks: Sequence) {
+        self.timeout = ticks;
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.link.local_addr()
+    }
+
+    pub fn clients(&self) -> Vec<SocketAddr> {
+        self.clients.keys().cloned().collect()
+    }
+
+    ///only send a client the entities filter returns true for
+    pub fn set_interest(&mut self, client: SocketAddr, filter: Interest) -> Result<(), &str> {
+        match self.clients.get_mut(&client) {
+            Some(connection) => {
+                connection.interest = Some(filter);
+                Ok(())
+            },
+            None => Err("client is not connected")
+        }
+    }
+
+    ///handle incoming connects and acks then send every client the current state of the world, call once per tick
+    ///clients that have timed out are dropped, a client that could not be sent its state is reported in NetError::Clients
+    ///without holding up the others, it is sent a fresh state from the same baseline next tick
+    ///states are not split, one larger than MAX_PACKET is reported once and then skipped quietly until the client's state fits again
+    pub fn update(&mut self, ecs: &ECS) -> Result<(), NetError> {
+        self.link.flush()?;
+        while let Some((bytes, from)) = self.link.recv()? {
+            let seq = self.seq;
+            match Cbor.decode::<Packet>(&bytes) {
+                Ok(Packet::Connect) => {
+                    self.clients.entry(from).or_insert_with(|| Connection{ acked: 0, sent: BTreeMap::new(), interest: None, heard: seq, oversized: false }).heard = seq;
+                },
+                Ok(Packet::Ack{ seq: acked }) => if let Some(connection) = self.clients.get_mut(&from) {
+                    connection.heard = seq;
+                    if acked > connection.acked && connection.sent.contains_key(&acked) {
+                        connection.acked = acked;
+                        connection.sent = connection.sent.split_off(&acked);
+                    }
+                },
+                //anything else is not meant for a server, or got mangled on the way
+                _ => continue
+            }
+        }
+        let world = self.registry.save(ecs)?;
+        self.seq += 1;
+        let (seq, timeout) = (self.seq, self.timeout);
+        self.clients.retain(|_, connection| seq - connection.heard <= timeout);
+        let empty = WorldData::empty();
+        let mut failed = Vec::new();
+        for (addr, connection) in self.clients.iter_mut() {
+            let mut state = world.clone();
+            if let Some(ref interest) = connection.interest {
+                state.retain(|entity| interest(ecs, entity));
+            }
+            let delta = WorldDelta::between(connection.sent.get(&connection.acked).unwrap_or(&empty), &state);
+            let packet = Packet::State{ seq, baseline: connection.acked, delta };
+            match send_state(&mut self.link, *addr, &packet) {
+                Err(NetError::TooLarge(_)) if connection.oversized => continue,
+                Err(e) => {
+                    connection.oversized = match e { NetError::TooLarge(_) => true, _ => connection.oversized };
+                    failed.push((*addr, e));
+                    continue;
+                },
+                Ok(()) => connection.oversized = false
+            }
+            connection.sent.insert(seq, state);
+            while connection.sent.len() > MAX_IN_FLIGHT {
+                let oldest = *connection.sent.keys().find(|seq| **seq != connection.acked).expect("more than one state is kept");
+                connection.sent.remove(&oldest);
+            }
+        }
+        if failed.is_empty() {
+            Ok(())
+        }else{
+            Err(NetError::Clients(failed))
+        }
+    }
+}
+
+//states are not split across datagrams, one that does not fit is refused rather than sent to be truncated
+fn send_state(link: &mut Link, to: SocketAddr, packet: &Packet) -> Result<(), NetError> {
+    let bytes = Cbor.encode(packet)?;
+    if bytes.len() > MAX_PACKET {
+        return Err(NetError::TooLarge(bytes.len()));
+    }
+    link.send(to, bytes)?;
+    Ok(())
+}
+
+///mirrors the replicated part of a server's world into a local ECS, server entities are given local indices
+pub struct Client {
+    link: Link,
+    server: SocketAddr,
+    registry: TypeRegistry,
+    received: BTreeMap<Sequence, WorldData>,
+    applied: Sequence,
+    //updates since the last state arrived
+    silent: Sequence,
+    map: EntityMap
+}
+
+impl Client {
+
+    ///bind a local port, the server is asked for state on every update until the first one arrives, registry must match the server's
+    ///a client that hears nothing for RECONNECT_AFTER updates asks again, so it rejoins a server that timed it out
+    pub fn connect<A: ToSocketAddrs>(server: A, registry: TypeRegistry, conditioner: LinkConditioner) -> io::Result<Client> {
+        let server = match server.to_socket_addrs()?.next() {
+            Some(addr) => addr,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no server address"))
+        };
+        let local = if server.is_ipv4() { "127.0.0.1:0" } else { "[::1]:0" };
+        let mut received = BTreeMap::new();
+        received.insert(0, WorldData::empty());
+        Ok(Client{ link: Link::bind(local, conditioner)?, server, registry, received, applied: 0, silent: 0, map: EntityMap::new() })
+    }
+
+    ///the newest server state applied to the local ECS
+    pub fn applied(&self) -> Sequence {
+        self.applied
+    }
+
+    ///server entity to local entity
+    pub fn entity_map(&self) -> &EntityMap {
+        &self.map
+    }
+
+    ///the local entity mirroring a server entity
+    pub fn local_entity(&self, server_entity: EntityIndex) -> Option<EntityIndex> {
+        self.map.get(server_entity)
+    }
+
+    ///apply every state that arrived since the last call to ecs and acknowledge the newest, call once per tick
+    pub fn update(&mut self, ecs: &mut ECS) -> Result<(), NetError> {
+        self.registry.register_components(ecs);
+        self.link.flush()?;
+        if self.applied == 0 || self.silent >= RECONNECT_AFTER {
+            self.link.send(self.server, Cbor.encode(&Packet::Connect)?)?;
+        }
+        let mut newest = None;
+        while let Some((bytes, from)) = self.link.recv()? {
+            if from != self.server {
+                continue;
+            }
+            if let Ok(Packet::State{ seq, baseline, delta }) = Cbor.decode::<Packet>(&bytes) {
+                //late packets and packets built on a state we have already thrown away are dropped, a newer one follows
+                if seq <= self.applied || !self.received.contains_key(&baseline) {
+                    continue;
+                }
+                let mut state = self.received[&baseline].clone();
+                delta.apply_to(&mut state);
+                let change = WorldDelta::between(&self.received[&self.applied], &state);
+                change.apply_mapped(&self.registry, ecs, &mut self.map)?;
+                self.received.insert(seq, state);
+                self.applied = seq;
+                newest = Some((seq, baseline));
+            }
+        }
+        if let Some((seq, baseline)) = newest {
+            //the server only builds on states we acked, anything older than the baseline it just used is no longer needed
+            //except the empty world, a server that dropped us starts over from it when we reconnect
+            self.received = self.received.split_off(&baseline);
+            self.received.insert(0, WorldData::empty());
+            self.link.send(self.server, Cbor.encode(&Packet::Ack{ seq })?)?;
+            self.silent = 0;
+        }else{
+            self.silent += 1;
+        }
+        Ok(())
+    }
+}
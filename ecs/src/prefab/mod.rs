@@ -0,0 +1,140 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use serde_value::Value;
+use serialize::Format;
+use serialize::Ron;
+use serialize::Json;
+use serialize::SerializeError;
+
+///reason a prefab could not be loaded or spawned
+#[derive(Clone, Debug, PartialEq)]
+pub enum PrefabError {
+    ///no prefab has been loaded under this name
+    Unknown(String),
+    ///the prefab inherits from itself through its bases
+    Cycle(String),
+    ///the prefab lists a component that has not been registered with a name
+    UnregisteredComponent(String),
+    ///the file could not be read
+    Io(String),
+    Serialize(SerializeError)
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrefabError::Unknown(name) => write!(f, "no prefab named {}", name),
+            PrefabError::Cycle(name) => write!(f, "prefab {} inherits from itself", name),
+            PrefabError::UnregisteredComponent(name) => write!(f, "component {} is not registered", name),
+            PrefabError::Io(e) => write!(f, "unable to read prefab file: {}", e),
+            PrefabError::Serialize(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl From<SerializeError> for PrefabError {
+    fn from(e: SerializeError) -> Self {
+        PrefabError::Serialize(e)
+    }
+}
+
+///a named bundle of component values, keyed by the names the components were registered under
+///fields set here override the same fields of the base prefab, everything else is inherited
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Prefab {
+    #[serde(default)]
+    pub base: Option<String>,
+    #[serde(default)]
+    pub components: BTreeMap<String, Value>
+}
+
+///every prefab that can be spawned, usually filled from data files at start up
+#[derive(Clone, Debug, Default)]
+pub struct PrefabLibrary {
+    prefabs: HashMap<String, Prefab>
+}
+
+impl PrefabLibrary {
+
+    pub fn new() -> PrefabLibrary {
+        PrefabLibrary{ prefabs: HashMap::new() }
+    }
+
+    ///add or replace a prefab
+    pub fn insert(&mut self, name: &str, prefab: Prefab) {
+        self.prefabs.insert(name.to_string(), prefab);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.prefabs.contains_key(name)
+    }
+
+    ///add every prefab in a map of name to prefab written in the given format
+    pub fn load<F: Format>(&mut self, format: &F, bytes: &[u8]) -> Result<(), PrefabError> {
+        let prefabs: BTreeMap<String, Prefab> = format.decode(bytes)?;
+        self.prefabs.extend(prefabs);
+        Ok(())
+    }
+
+    ///add every prefab in a .ron or .json file
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), PrefabError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| PrefabError::Io(e.to_string()))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => self.load(&Json, &bytes),
+            _ => self.load(&Ron, &bytes)
+        }
+    }
+
+    ///the component values of a prefab with every base applied underneath it
+    pub fn resolve(&self, name: &str) -> Result<BTreeMap<String, Value>, PrefabError> {
+        let mut chain = Vec::new();
+        let mut next = Some(name.to_string());
+        while let Some(current) = next {
+            if chain.contains(&current) {
+                return Err(PrefabError::Cycle(current));
+            }
+            let prefab = match self.prefabs.get(&current) {
+                Some(prefab) => prefab,
+                None => return Err(PrefabError::Unknown(current))
+            };
+            next = prefab.base.clone();
+            chain.push(current);
+        }
+        let mut components = BTreeMap::new();
+        for current in chain.iter().rev() {
+            for (component, value) in self.prefabs[current].components.iter() {
+                let merged = match components.remove(component) {
+                    Some(base) => merge(base, value.clone()),
+                    None => value.clone()
+                };
+                components.insert(component.clone(), merged);
+            }
+        }
+        Ok(components)
+    }
+}
+
+//struct fields override field by field, anything else is replaced outright
+fn merge(base: Value, over: Value) -> Value {
+    match (base, over) {
+        (Value::Map(mut base), Value::Map(over)) => {
+            for (key, value) in over {
+                let merged = match base.remove(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => value
+                };
+                base.insert(key, merged);
+            }
+            Value::Map(base)
+        },
+        (_, over) => over
+    }
+}
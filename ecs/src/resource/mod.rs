@@ -3,24 +3,55 @@ use std::cell::Ref;
 use std::cell::RefCell;
 use downcast_rs::Downcast;
 use std::any::TypeId;
+use std::any::Any;
+use std::sync::Arc;
 use std::sync::RwLock;
 use std::collections::HashMap;
 use std::sync::RwLockReadGuard;
 use std::sync::RwLockWriteGuard;
+use std::any::type_name;
+use std::time::Duration;
+use lock;
+use lock::AccessError;
+use lock::BorrowTracker;
+use lock::BorrowToken;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::mem;
+use std::mem::ManuallyDrop;
+use std::ptr;
+use std::thread;
+use std::thread::ThreadId;
+use ECS;
 
 ///centralized easily accessible storage for shared resources
 pub struct ResourceMap{
-    map: HashMap<TypeId, Box<ResourceEntry>>
+    map: HashMap<TypeId, Box<ResourceEntry>>,
+    non_send: HashMap<TypeId, Box<ResourceEntry>>,
+    rollback: HashMap<TypeId, (SnapshotFn, RestoreFn)>,
+    main_thread: ThreadId
 }
+///copies a resource selected for rollback, None if it is not present
+pub type SnapshotFn = fn(&ResourceMap) -> Option<Arc<Any + Send + Sync>>;
+///puts a resource selected for rollback back to a copy, None removes it
+pub type RestoreFn = fn(&mut ResourceMap, Option<&Arc<Any + Send + Sync>>);
 ///convenience trait allowing for casting to appropriate type
-pub trait ResourceEntry: Downcast {}
+pub trait ResourceEntry: Downcast + Send + Sync {}
 impl_downcast!(ResourceEntry);
 ///Entry type for the resource map
-pub struct Resource<T>(RwLock<T>);
+pub struct Resource<T>(RwLock<T>, BorrowTracker);
+///Entry type for resources that are not Send or Sync, only the main thread may touch the value
+///a world dropped on any other thread LEAKS its non send resources, their destructors never run,
+///remove them with remove_non_send_resource on the main thread before handing the world to another thread to drop
+pub struct NonSend<T>{
+    value: ManuallyDrop<RefCell<T>>,
+    owner: ThreadId
+}
 
 impl ResourceMap{
     ///get a mutable reference to the stored resource
-    pub fn get_write_resource<T:'static>(&self) -> Result<ResourceWriteHandle<T>, &str>{
+    #[track_caller]
+    pub fn get_write_resource<T:'static + Send + Sync>(&self) -> Result<ResourceWriteHandle<T>, &str>{
         if let Some(x) = self.map.get(&TypeId::of::<T>()){
             if let Some(downcast) = x.downcast_ref::<Resource<T>>(){
                 Ok(downcast.get_mut())
@@ -33,7 +64,8 @@ impl ResourceMap{
         }
     }
     ///get an immutable reference to the stored resource
-    pub fn get_read_resource<T:'static>(&self) -> Result<ResourceReadHandle<T>, &str>{
+    #[track_caller]
+    pub fn get_read_resource<T:'static + Send + Sync>(&self) -> Result<ResourceReadHandle<T>, &str>{
         if let Some(entry) = self.map.get(&TypeId::of::<T>()) {
             if let Some(t) = entry.downcast_ref::<Resource<T>>() {
                 Ok(t.get())
@@ -44,12 +76,41 @@ impl ResourceMap{
             Err("resource does not exist")
         }
     }
-    ///insert a new resource into the resource map
-    pub fn insert_resource<T:'static>(&mut self, resource: T){
-        self.map.insert(TypeId::of::<T>(), Box::new(Resource(RwLock::new(resource))));
+    ///get a mutable reference to the stored resource, waiting up to timeout for it to become free, None tries exactly once
+    #[track_caller]
+    pub fn get_write_resource_within<T:'static + Send + Sync>(&self, timeout: Option<Duration>) -> Result<ResourceWriteHandle<T>, AccessError>{
+        match self.map.get(&TypeId::of::<T>()).and_then(|x| x.downcast_ref::<Resource<T>>()) {
+            Some(resource) => resource.get_mut_within(timeout),
+            None => Err(AccessError::Missing(type_name::<T>()))
+        }
+    }
+    ///get an immutable reference to the stored resource, waiting up to timeout for it to become free, None tries exactly once
+    #[track_caller]
+    pub fn get_read_resource_within<T:'static + Send + Sync>(&self, timeout: Option<Duration>) -> Result<ResourceReadHandle<T>, AccessError>{
+        match self.map.get(&TypeId::of::<T>()).and_then(|x| x.downcast_ref::<Resource<T>>()) {
+            Some(resource) => resource.get_within(timeout),
+            None => Err(AccessError::Missing(type_name::<T>()))
+        }
+    }
+    ///insert a new resource into the resource map, replacing any existing resource of the same type
+    pub fn insert_resource<T:'static + Send + Sync>(&mut self, resource: T){
+        self.map.insert(TypeId::of::<T>(), Box::new(Resource(RwLock::new(resource), BorrowTracker::new())));
+    }
+    ///insert a new resource into the resource map, failing if one of the same type is already stored
+    pub fn try_insert_resource<T:'static + Send + Sync>(&mut self, resource: T) -> Result<(), &str>{
+        if self.contains_resource::<T>() {
+            Err("resource already exists")
+        }else{
+            self.insert_resource(resource);
+            Ok(())
+        }
+    }
+    ///check whether a resource of this type is stored, either as a shared or a non send resource
+    pub fn contains_resource<T:'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>()) || self.non_send.contains_key(&TypeId::of::<T>())
     }
     ///remove a resource from the resource map
-    pub fn remove_resource<T:'static>(&mut self) -> Result<Resource<T>, &str> {
+    pub fn remove_resource<T:'static + Send + Sync>(&mut self) -> Result<Resource<T>, &str> {
         match self.map.remove(&TypeId::of::<T>()) {
             Some(x) => {
                 match x.downcast::<Resource<T>>() {
@@ -60,33 +121,253 @@ impl ResourceMap{
             None => Err("resource does not exist")
         }
     }
+    ///include resources of this type in world snapshots
+    pub fn register_rollback<T:'static + Send + Sync + Clone>(&mut self){
+        self.rollback.insert(TypeId::of::<T>(), (snapshot_resource::<T>, restore_resource::<T>));
+    }
+    ///copy every resource registered for rollback
+    pub fn snapshot(&self) -> HashMap<TypeId, Option<Arc<Any + Send + Sync>>> {
+        self.rollback.iter().map(|(id, fns)| (*id, (fns.0)(self))).collect()
+    }
+    ///put every resource registered for rollback back to a snapshot
+    pub fn restore(&mut self, snapshot: &HashMap<TypeId, Option<Arc<Any + Send + Sync>>>) {
+        let restores = self.rollback.iter().map(|(id, fns)| (*id, fns.1)).collect::<Vec<_>>();
+        for (id, restore) in restores {
+            if let Some(copy) = snapshot.get(&id) {
+                restore(self, copy.as_ref());
+            }
+        }
+    }
+    ///the thread the resource map was created on, the only thread allowed to use non send resources
+    ///nothing pins work to it, the ECS has no scheduler, so whoever runs systems must keep those using non send resources here
+    pub fn main_thread(&self) -> ThreadId {
+        self.main_thread
+    }
+    ///check whether the calling thread is the main thread
+    pub fn is_main_thread(&self) -> bool {
+        thread::current().id() == self.main_thread
+    }
+    ///insert a resource that is not Send or Sync, replacing any existing one of the same type, must be called from the main thread
+    pub fn insert_non_send_resource<T:'static>(&mut self, resource: T) -> Result<(), AccessError>{
+        if !self.is_main_thread() {
+            return Err(AccessError::WrongThread(type_name::<T>()));
+        }
+        self.non_send.insert(TypeId::of::<T>(), Box::new(NonSend{ value: ManuallyDrop::new(RefCell::new(resource)), owner: self.main_thread }));
+        Ok(())
+    }
+    ///check whether a non send resource of this type is stored
+    pub fn contains_non_send_resource<T:'static>(&self) -> bool {
+        self.non_send.contains_key(&TypeId::of::<T>())
+    }
+    ///check whether any non send resources are stored, systems touching the world should then run on the main thread
+    pub fn has_non_send_resources(&self) -> bool {
+        !self.non_send.is_empty()
+    }
+    ///get an immutable reference to a non send resource, fails on any thread but the main thread or while it is mutably borrowed
+    pub fn get_non_send_resource<T:'static>(&self) -> Result<NonSendReadHandle<T>, AccessError>{
+        self.non_send_entry::<T>()?.get()
+    }
+    ///get a mutable reference to a non send resource, fails on any thread but the main thread or while it is borrowed
+    pub fn get_non_send_resource_mut<T:'static>(&self) -> Result<NonSendWriteHandle<T>, AccessError>{
+        self.non_send_entry::<T>()?.get_mut()
+    }
+    ///remove a non send resource, must be called from the main thread
+    pub fn remove_non_send_resource<T:'static>(&mut self) -> Result<T, AccessError>{
+        self.non_send_entry::<T>()?.check()?;
+        match self.non_send.remove(&TypeId::of::<T>()).map(|x| x.downcast::<NonSend<T>>()) {
+            Some(Ok(x)) => Ok(x.into_inner()),
+            _ => Err(AccessError::Missing(type_name::<T>()))
+        }
+    }
+
+    fn non_send_entry<T:'static>(&self) -> Result<&NonSend<T>, AccessError>{
+        match self.non_send.get(&TypeId::of::<T>()).and_then(|x| x.downcast_ref::<NonSend<T>>()) {
+            Some(entry) => Ok(entry),
+            None => Err(AccessError::Missing(type_name::<T>()))
+        }
+    }
+}
+
+fn snapshot_resource<T:'static + Send + Sync + Clone>(resources: &ResourceMap) -> Option<Arc<Any + Send + Sync>> {
+    match resources.get_read_resource::<T>() {
+        Ok(resource) => Some(Arc::new((*resource).clone())),
+        Err(_) => None
+    }
+}
+
+fn restore_resource<T:'static + Send + Sync + Clone>(resources: &mut ResourceMap, copy: Option<&Arc<Any + Send + Sync>>) {
+    match copy.and_then(|c| c.downcast_ref::<T>()) {
+        Some(resource) => resources.insert_resource(resource.clone()),
+        None => {
+            let _ = resources.remove_resource::<T>();
+        }
+    }
+}
+
+///builds a resource from the current state of the ECS, implemented for every type that implements Default
+pub trait FromWorld {
+    fn from_world(ecs: &ECS) -> Self;
+}
+
+impl<T: Default> FromWorld for T {
+    fn from_world(_ecs: &ECS) -> T {
+        T::default()
+    }
 }
 
 impl Default for ResourceMap {
     fn default() -> Self {
         ResourceMap{
-            map: HashMap::new()
+            map: HashMap::new(),
+            non_send: HashMap::new(),
+            rollback: HashMap::new(),
+            main_thread: thread::current().id()
         }
     }
 }
 
 impl<T> Resource<T> {
     ///get a mutable reference to the stored resource
+    #[track_caller]
     pub fn get_mut(&self) -> ResourceWriteHandle<T> {
-        ResourceWriteHandle{r: self.0.write().unwrap()}
+        let (r, borrow) = lock::write(&self.0, &self.1, type_name::<T>());
+        ResourceWriteHandle{r, _borrow: borrow}
     }
     ///get a immutable reference to the stored resource
+    #[track_caller]
     pub fn get(&self) -> ResourceReadHandle<T> {
-        ResourceReadHandle{r: self.0.read().unwrap()}
+        let (r, borrow) = lock::read(&self.0, &self.1, type_name::<T>());
+        ResourceReadHandle{r, _borrow: borrow}
+    }
+    ///get a mutable reference to the stored resource, waiting up to timeout for it to become free
+    #[track_caller]
+    pub fn get_mut_within(&self, timeout: Option<Duration>) -> Result<ResourceWriteHandle<T>, AccessError> {
+        let (r, borrow) = lock::write_within(&self.0, &self.1, timeout, type_name::<T>())?;
+        Ok(ResourceWriteHandle{r, _borrow: borrow})
+    }
+    ///get an immutable reference to the stored resource, waiting up to timeout for it to become free
+    #[track_caller]
+    pub fn get_within(&self, timeout: Option<Duration>) -> Result<ResourceReadHandle<T>, AccessError> {
+        let (r, borrow) = lock::read_within(&self.0, &self.1, timeout, type_name::<T>())?;
+        Ok(ResourceReadHandle{r, _borrow: borrow})
+    }
+    ///describe which threads and call sites currently hold this resource, always empty in release builds
+    pub fn borrow_report(&self) -> String {
+        self.1.report(type_name::<T>())
+    }
+}
+
+impl<T:'static + Send + Sync> ResourceEntry for Resource<T> {}
+
+impl<T> NonSend<T> {
+    ///fails unless called from the thread that owns the resource
+    pub fn check(&self) -> Result<(), AccessError> {
+        if thread::current().id() == self.owner {
+            Ok(())
+        }else{
+            Err(AccessError::WrongThread(type_name::<T>()))
+        }
+    }
+    ///get an immutable reference to the stored resource
+    pub fn get(&self) -> Result<NonSendReadHandle<T>, AccessError> {
+        self.check()?;
+        match self.value.try_borrow() {
+            Ok(r) => Ok(NonSendReadHandle{r}),
+            Err(_) => Err(AccessError::Busy(type_name::<T>(), Vec::new()))
+        }
+    }
+    ///get a mutable reference to the stored resource
+    pub fn get_mut(&self) -> Result<NonSendWriteHandle<T>, AccessError> {
+        self.check()?;
+        match self.value.try_borrow_mut() {
+            Ok(r) => Ok(NonSendWriteHandle{r}),
+            Err(_) => Err(AccessError::Busy(type_name::<T>(), Vec::new()))
+        }
+    }
+
+    fn into_inner(self) -> T {
+        //safe as self is forgotten straight after, so the value is only dropped once
+        let value = unsafe { ptr::read(&self.value) };
+        mem::forget(self);
+        ManuallyDrop::into_inner(value).into_inner()
     }
 }
 
-impl<T:'static> ResourceEntry for Resource<T> {}
+//the value is only ever reached through check, so it never leaves the owning thread
+unsafe impl<T> Send for NonSend<T> {}
+unsafe impl<T> Sync for NonSend<T> {}
+
+impl<T:'static> ResourceEntry for NonSend<T> {}
+
+impl<T> Drop for NonSend<T> {
+    //a world dropped on another thread leaks its non send resources rather than dropping them on the wrong thread
+    fn drop(&mut self) {
+        if thread::current().id() == self.owner {
+            unsafe { ManuallyDrop::drop(&mut self.value) }
+        }else{
+            warn!("leaked non send resource {}, it was dropped off the thread that owns it", type_name::<T>());
+        }
+    }
+}
 
 pub struct ResourceReadHandle<'l, T> {
-    pub r: RwLockReadGuard<'l, T>
+    pub r: RwLockReadGuard<'l, T>,
+    _borrow: BorrowToken<'l>
 }
 
 pub struct ResourceWriteHandle<'l, T> {
-    pub r: RwLockWriteGuard<'l, T>
-}
\ No newline at end of file
+    pub r: RwLockWriteGuard<'l, T>,
+    _borrow: BorrowToken<'l>
+}
+impl<'l, T> Deref for ResourceReadHandle<'l, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.r.deref()
+    }
+}
+
+impl<'l, T> Deref for ResourceWriteHandle<'l, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.r.deref()
+    }
+}
+
+impl<'l, T> DerefMut for ResourceWriteHandle<'l, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.r.deref_mut()
+    }
+}
+
+///handles to non send resources borrow a RefCell so they can not be sent to another thread either
+pub struct NonSendReadHandle<'l, T> {
+    r: Ref<'l, T>
+}
+
+pub struct NonSendWriteHandle<'l, T> {
+    r: RefMut<'l, T>
+}
+
+impl<'l, T> Deref for NonSendReadHandle<'l, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.r.deref()
+    }
+}
+
+impl<'l, T> Deref for NonSendWriteHandle<'l, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.r.deref()
+    }
+}
+
+impl<'l, T> DerefMut for NonSendWriteHandle<'l, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.r.deref_mut()
+    }
+}
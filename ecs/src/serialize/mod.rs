@@ -0,0 +1,490 @@
+use std::fmt;
+use std::any::TypeId;
+use std::hash::Hash;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_value;
+use serde_value::Value;
+use ron;
+use serde_cbor;
+use serde_json;
+use ECS;
+use archetype;
+use component::Component;
+use component::ComponentRef;
+use component::Iter;
+use component::Storage;
+use entity::EntityIndex;
+use entity::management::EntityAllocator;
+use entity::mapping::EntityMap;
+use entity::mapping::MapEntities;
+use checksum::ChecksumReport;
+use checksum::HashFn;
+use checksum::hash_components;
+
+///reason a world could not be saved or loaded
+#[derive(Clone, Debug, PartialEq)]
+pub enum SerializeError {
+    ///the data names a component or resource that has not been registered
+    Unregistered(String),
+    ///a second type was registered under a name that is already taken
+    Duplicate(String),
+    ///a value could not be turned into the format
+    Encode(String),
+    ///the bytes or a stored value could not be read back
+    Decode(String),
+    ///the world's layout cannot be read this way
+    Unsupported(&'static str)
+}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SerializeError::Unregistered(name) => write!(f, "{} is not registered", name),
+            SerializeError::Duplicate(name) => write!(f, "{} is registered more than once", name),
+            SerializeError::Encode(e) => write!(f, "unable to encode: {}", e),
+            SerializeError::Decode(e) => write!(f, "unable to decode: {}", e),
+            SerializeError::Unsupported(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+///every component of one type, keyed by the entity it belongs to
+pub type ComponentData = Vec<(EntityIndex, Value)>;
+
+///format independent snapshot of an ECS, components and resources are keyed by their registered names
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WorldData {
+    pub entities: EntityAllocator,
+    pub components: BTreeMap<String, ComponentData>,
+    pub resources: BTreeMap<String, Value>
+}
+
+impl WorldData {
+
+    ///a world with no entities
+    pub fn empty() -> WorldData {
+        WorldData{ entities: EntityAllocator::new(), components: BTreeMap::new(), resources: BTreeMap::new() }
+    }
+
+    ///drop every entity, and its components, that keep returns false for
+    pub fn retain<F: Fn(EntityIndex) -> bool>(&mut self, keep: F) {
+        let dropped = self.entities.live().filter(|entity| !keep(*entity)).collect::<Vec<_>>();
+        for entity in dropped {
+            self.entities.entity_list[entity.index()].is_live = false;
+        }
+        let entities = &self.entities;
+        for components in self.components.values_mut() {
+            components.retain(|c| entities.entity_list[c.0.index()].is_live);
+        }
+    }
+}
+
+impl Default for WorldData {
+    fn default() -> Self {
+        WorldData::empty()
+    }
+}
+
+///a serde data format the world can be written in
+pub trait Format {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, SerializeError>;
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, SerializeError>;
+}
+
+///human readable Rusty Object Notation, for level files and debugging
+pub struct Ron;
+
+///compact binary CBOR, for save games
+pub struct Cbor;
+
+///human readable JSON, for data produced by external tools
+pub struct Json;
+
+impl Format for Ron {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, SerializeError> {
+        //newtypes are written bare, a type erased Value would otherwise read them back as a one element tuple
+        let config = ron::ser::PrettyConfig::new().extensions(ron::extensions::Extensions::UNWRAP_NEWTYPES);
+        match ron::ser::to_string_pretty(value, config) {
+            Ok(s) => Ok(s.into_bytes()),
+            Err(e) => Err(SerializeError::Encode(e.to_string()))
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, SerializeError> {
+        ron::de::from_bytes(bytes).map_err(|e| SerializeError::Decode(e.to_string()))
+    }
+}
+
+impl Format for Json {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, SerializeError> {
+        serde_json::to_vec_pretty(value).map_err(|e| SerializeError::Encode(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, SerializeError> {
+        serde_json::from_slice(bytes).map_err(|e| SerializeError::Decode(e.to_string()))
+    }
+}
+
+impl Format for Cbor {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, SerializeError> {
+        serde_cbor::to_vec(value).map_err(|e| SerializeError::Encode(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, SerializeError> {
+        serde_cbor::from_slice(bytes).map_err(|e| SerializeError::Decode(e.to_string()))
+    }
+}
+
+///deserializes a single component and adds it to an entity, running its on_add hook
+pub type InsertFn = fn(&mut ECS, EntityIndex, Value) -> Result<(), SerializeError>;
+
+///deserializes the components of one type and stores them, moving them onto the entities in the map if one is given
+pub type LoadFn = fn(&mut ECS, ComponentData, Option<&EntityMap>) -> Result<(), SerializeError>;
+
+///type erased save and load functions for a registered component
+pub struct ComponentRegistration {
+    register: fn(&mut ECS),
+    insert: InsertFn,
+    remove: fn(&mut ECS, EntityIndex),
+    save: fn(&ECS) -> Result<Option<ComponentData>, SerializeError>,
+    load: LoadFn,
+    //decodes every component without storing any, so a bad save is caught before the ECS is touched
+    validate: fn(&ComponentData) -> Result<(), SerializeError>,
+    //rewrites the entity references of every stored component, only set for mapped components
+    remap: Option<fn(&mut ECS, &EntityMap)>
+}
+
+///type erased save and load functions for a registered resource
+pub struct ResourceRegistration {
+    save: fn(&ECS) -> Result<Option<Value>, SerializeError>,
+    load: fn(&mut ECS, Value) -> Result<(), SerializeError>,
+    validate: fn(&Value) -> Result<(), SerializeError>,
+    contains: fn(&ECS) -> bool
+}
+
+///maps stable names to the component and resource types that can be saved or checksummed, TypeId is not stable between builds so it is never written out
+pub struct TypeRegistry {
+    components: BTreeMap<String, ComponentRegistration>,
+    resources: BTreeMap<String, ResourceRegistration>,
+    names: HashMap<TypeId, String>,
+    checksums: BTreeMap<String, HashFn>
+}
+
+impl TypeRegistry {
+
+    pub fn new() -> TypeRegistry {
+        TypeRegistry{ components: BTreeMap::new(), resources: BTreeMap::new(), names: HashMap::new(), checksums: BTreeMap::new() }
+    }
+
+    ///include a hashable component type in the world checksum under a name every peer uses
+    ///a type also registered for saving has to be hashed under the name it is saved under
+    pub fn register_hashed_component<T: Component + Hash>(&mut self, name: &str) -> Result<(), SerializeError> {
+        let taken = match self.names.get(&TypeId::of::<T>()) {
+            Some(saved) => saved != name,
+            None => self.components.contains_key(name) || self.resources.contains_key(name)
+        };
+        if taken || self.checksums.contains_key(name) {
+            return Err(SerializeError::Duplicate(name.to_string()));
+        }
+        self.checksums.insert(name.to_string(), hash_components::<T>);
+        Ok(())
+    }
+
+    ///per part hashes of a world over every component type registered with register_hashed_component
+    pub fn checksum_report(&self, ecs: &ECS) -> Result<ChecksumReport, SerializeError> {
+        if ecs.archetypes.is_some() {
+            return Err(SerializeError::Unsupported(archetype::UNSUPPORTED));
+        }
+        Ok(ChecksumReport::new(ecs, &self.checksums))
+    }
+
+    ///opt a component type in to saving under a name that must stay the same across builds
+    pub fn register_component<T: Component + Serialize + DeserializeOwned>(&mut self, name: &str) -> Result<(), SerializeError> {
+        self.claim::<T>(name)?;
+        self.components.insert(name.to_string(), ComponentRegistration{
+            register: register_component::<T>,
+            insert: insert_component::<T>,
+            remove: remove_component::<T>,
+            save: save_component::<T>,
+            load: load_component::<T>,
+            validate: validate_components::<T>,
+            remap: None
+        });
+        Ok(())
+    }
+
+    ///opt a component type that refers to other entities in to saving, its references are rewritten when it is merged into another ECS
+    pub fn register_mapped_component<T: Component + MapEntities + Serialize + DeserializeOwned>(&mut self, name: &str) -> Result<(), SerializeError> {
+        self.claim::<T>(name)?;
+        self.components.insert(name.to_string(), ComponentRegistration{
+            register: register_component::<T>,
+            insert: insert_component::<T>,
+            remove: remove_component::<T>,
+            save: save_component::<T>,
+            load: load_mapped_component::<T>,
+            validate: validate_components::<T>,
+            remap: Some(remap_component::<T>)
+        });
+        Ok(())
+    }
+
+    ///opt a resource type in to saving under a name that must stay the same across builds
+    pub fn register_resource<T: 'static + Send + Sync + Serialize + DeserializeOwned>(&mut self, name: &str) -> Result<(), SerializeError> {
+        self.claim::<T>(name)?;
+        self.resources.insert(name.to_string(), ResourceRegistration{
+            save: save_resource::<T>,
+            load: load_resource::<T>,
+            validate: validate_resource::<T>,
+            contains: contains_resource::<T>
+        });
+        Ok(())
+    }
+
+    ///the function that adds a component registered under name to an entity
+    pub fn inserter(&self, name: &str) -> Option<InsertFn> {
+        self.components.get(name).map(|registration| registration.insert)
+    }
+
+    ///the function that loads every component of the type registered under name
+    pub fn loader(&self, name: &str) -> Option<LoadFn> {
+        self.components.get(name).map(|registration| registration.load)
+    }
+
+    ///the function that takes a component registered under name off an entity
+    pub fn remover(&self, name: &str) -> Option<fn(&mut ECS, EntityIndex)> {
+        self.components.get(name).map(|registration| registration.remove)
+    }
+
+    ///rewrite the entity references held by every component registered with register_mapped_component
+    pub fn remap(&self, ecs: &mut ECS, map: &EntityMap) {
+        for remap in self.components.values().filter_map(|registration| registration.remap) {
+            remap(ecs, map);
+        }
+    }
+
+    ///check whether a component type has been registered under name
+    pub fn has_component(&self, name: &str) -> bool {
+        self.components.contains_key(name)
+    }
+
+    ///the name a type was registered under
+    pub fn name_of<T: 'static>(&self) -> Option<&str> {
+        self.names.get(&TypeId::of::<T>()).map(|name| name.as_str())
+    }
+
+    ///snapshot the entity allocator, every registered component and every registered resource that is present
+    pub fn save(&self, ecs: &ECS) -> Result<WorldData, SerializeError> {
+        if ecs.archetypes.is_some() {
+            return Err(SerializeError::Unsupported(archetype::UNSUPPORTED));
+        }
+        let mut components = BTreeMap::new();
+        for (name, registration) in self.components.iter() {
+            if let Some(data) = (registration.save)(ecs)? {
+                components.insert(name.clone(), data);
+            }
+        }
+        let mut resources = BTreeMap::new();
+        for (name, registration) in self.resources.iter() {
+            if let Some(value) = (registration.save)(ecs)? {
+                resources.insert(name.clone(), value);
+            }
+        }
+        Ok(WorldData{ entities: ecs.entity_list.clone(), components, resources })
+    }
+
+    ///build a new ECS from a snapshot, every registered component type is registered even if the snapshot holds none of it
+    pub fn load(&self, data: WorldData) -> Result<ECS, SerializeError> {
+        self.check(&data)?;
+        let mut ecs = ECS::new();
+        self.register_components(&mut ecs);
+        ecs.size = data.entities.entity_list.iter().filter(|e| e.is_live).count();
+        ecs.entity_list = data.entities;
+        for (name, components) in data.components {
+            (self.components[&name].load)(&mut ecs, components, None)?;
+        }
+        for (name, value) in data.resources {
+            (self.resources[&name].load)(&mut ecs, value)?;
+        }
+        ecs.rebuild_network_index();
+        Ok(ecs)
+    }
+
+    ///bring every live entity of a snapshot into an existing ECS as newly allocated entities
+    ///components run their on_add hooks and have their entity references rewritten if they were registered as mapped,
+    ///resources are only added if the ECS does not already have one of the same type
+    pub fn merge(&self, ecs: &mut ECS, data: WorldData) -> Result<EntityMap, SerializeError> {
+        self.check(&data)?;
+        self.register_components(ecs);
+        let mut map = EntityMap::new();
+        for entity in data.entities.live() {
+            map.insert(entity, ecs.allocate_new_entity());
+        }
+        for (name, components) in data.components {
+            (self.components[&name].load)(ecs, components, Some(&map))?;
+        }
+        for (name, value) in data.resources {
+            let registration = &self.resources[&name];
+            if !(registration.contains)(ecs) {
+                (registration.load)(ecs, value)?;
+            }
+        }
+        Ok(map)
+    }
+
+    //fail before touching the ECS if the snapshot names anything that is not registered or holds anything that does not decode
+    //run before the ECS is touched, so a bad save is refused rather than half applied
+    fn check(&self, data: &WorldData) -> Result<(), SerializeError> {
+        data.entities.validate().map_err(|e| SerializeError::Decode(e.to_string()))?;
+        for (name, components) in data.components.iter() {
+            let registration = match self.components.get(name) {
+                Some(registration) => registration,
+                None => return Err(SerializeError::Unregistered(name.clone()))
+            };
+            //a component on a dead slot would be handed to whichever entity is allocated there next
+            if let Some((entity, _)) = components.iter().find(|(entity, _)| !data.entities.is_live(*entity)) {
+                return Err(SerializeError::Decode(format!("{} stored against dead entity {:?}", name, entity)));
+            }
+            (registration.validate)(components)?;
+        }
+        for (name, value) in data.resources.iter() {
+            match self.resources.get(name) {
+                Some(registration) => (registration.validate)(value)?,
+                None => return Err(SerializeError::Unregistered(name.clone()))
+            }
+        }
+        Ok(())
+    }
+
+    ///register storage for every registered component type the ECS does not have yet
+    pub fn register_components(&self, ecs: &mut ECS) {
+        for registration in self.components.values() {
+            (registration.register)(ecs);
+        }
+    }
+
+    fn claim<T: 'static>(&mut self, name: &str) -> Result<(), SerializeError> {
+        if self.components.contains_key(name) || self.resources.contains_key(name) || self.names.contains_key(&TypeId::of::<T>()) {
+            return Err(SerializeError::Duplicate(name.to_string()));
+        }
+        self.names.insert(TypeId::of::<T>(), name.to_string());
+        Ok(())
+    }
+}
+
+impl Default for TypeRegistry {
+    fn default() -> Self {
+        TypeRegistry::new()
+    }
+}
+
+fn register_component<T: Component>(ecs: &mut ECS) {
+    if ecs.storage.get::<T>().is_err() {
+        ecs.register_new_component::<T>().expect("component was not registered");
+    }
+}
+
+fn insert_component<T: Component + DeserializeOwned>(ecs: &mut ECS, entity: EntityIndex, value: Value) -> Result<(), SerializeError> {
+    let component = value.deserialize_into::<T>().map_err(|e| SerializeError::Decode(e.to_string()))?;
+    ecs.add_component(entity, component).map_err(|e| SerializeError::Decode(e.to_string()))?;
+    Ok(())
+}
+
+fn remove_component<T: Component>(ecs: &mut ECS, entity: EntityIndex) {
+    let _ = ecs.remove_component::<T>(entity);
+}
+
+fn save_component<T: Component + Serialize>(ecs: &ECS) -> Result<Option<ComponentData>, SerializeError> {
+    let store = match ecs.storage.get::<T>() {
+        Ok(store) => store,
+        Err(_) => return Ok(None)
+    };
+    let handle = store.read_handle();
+    let mut iter = handle.get_iterator();
+    let mut components = Vec::new();
+    while let Some((component, index)) = iter.next_element(None) {
+        if let Some(entity) = ecs.entity_list.entity(index) {
+            let value = serde_value::to_value(component.component()).map_err(|e| SerializeError::Encode(e.to_string()))?;
+            components.push((entity, value));
+        }
+    }
+    Ok(Some(components))
+}
+
+fn remap_component<T: Component + MapEntities>(ecs: &mut ECS, map: &EntityMap) {
+    if let Some(ref mut archetypes) = ecs.archetypes {
+        archetypes.for_each_mut(|_, component: &mut T| component.map_entities(map));
+    }else{
+        let entities = ecs.entity_list.live().collect::<Vec<_>>();
+        let storage = match ecs.get_mut::<T>() {
+            Ok(storage) => storage,
+            Err(_) => return
+        };
+        for entity in entities {
+            if let Some(component) = storage.get_mut(entity) {
+                component.map_entities(map);
+            }
+        }
+    }
+}
+
+fn validate_components<T: Component + DeserializeOwned>(components: &ComponentData) -> Result<(), SerializeError> {
+    for (_, value) in components.iter() {
+        value.clone().deserialize_into::<T>().map_err(|e| SerializeError::Decode(e.to_string()))?;
+    }
+    Ok(())
+}
+
+fn load_component<T: Component + DeserializeOwned>(ecs: &mut ECS, components: ComponentData, map: Option<&EntityMap>) -> Result<(), SerializeError> {
+    import_components::<T, _>(ecs, components, map, |_, _| {})
+}
+
+fn load_mapped_component<T: Component + MapEntities + DeserializeOwned>(ecs: &mut ECS, components: ComponentData, map: Option<&EntityMap>) -> Result<(), SerializeError> {
+    import_components::<T, _>(ecs, components, map, T::map_entities)
+}
+
+//without a map the world is being restored, so components are put straight back into storage and hooks do not run
+fn import_components<T, F>(ecs: &mut ECS, components: ComponentData, map: Option<&EntityMap>, map_entities: F) -> Result<(), SerializeError>
+    where T: Component + DeserializeOwned, F: Fn(&mut T, &EntityMap) {
+    for (index, value) in components {
+        let mut component = value.deserialize_into::<T>().map_err(|e| SerializeError::Decode(e.to_string()))?;
+        match map {
+            Some(map) => {
+                let entity = match map.get(index) {
+                    Some(entity) => entity,
+                    None => return Err(SerializeError::Decode(format!("component stored against dead entity {:?}", index)))
+                };
+                map_entities(&mut component, map);
+                ecs.add_component(entity, component).map_err(|e| SerializeError::Decode(e.to_string()))?;
+            },
+            None => {
+                let storage = ecs.get_mut::<T>().map_err(|e| SerializeError::Decode(e.to_string()))?;
+                storage.insert(index, component).map_err(|e| SerializeError::Decode(e.0.to_string()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn save_resource<T: 'static + Send + Sync + Serialize>(ecs: &ECS) -> Result<Option<Value>, SerializeError> {
+    match ecs.get_resource::<T>() {
+        Ok(resource) => serde_value::to_value(&*resource).map(Some).map_err(|e| SerializeError::Encode(e.to_string())),
+        Err(_) => Ok(None)
+    }
+}
+
+fn contains_resource<T: 'static + Send + Sync>(ecs: &ECS) -> bool {
+    ecs.resources.contains_resource::<T>()
+}
+
+fn validate_resource<T: 'static + Send + Sync + DeserializeOwned>(value: &Value) -> Result<(), SerializeError> {
+    value.clone().deserialize_into::<T>().map(|_| ()).map_err(|e| SerializeError::Decode(e.to_string()))
+}
+
+fn load_resource<T: 'static + Send + Sync + DeserializeOwned>(ecs: &mut ECS, value: Value) -> Result<(), SerializeError> {
+    let resource = value.deserialize_into::<T>().map_err(|e| SerializeError::Decode(e.to_string()))?;
+    ecs.insert_new_resource(resource);
+    Ok(())
+}
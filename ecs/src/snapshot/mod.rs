@@ -0,0 +1,118 @@
+use std::any::Any;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use ECS;
+use archetype;
+use entity::management::EntityAllocator;
+
+///an in memory copy of the allocator, every component store and the resources registered for rollback
+///component stores are shared between snapshots until they are written to, so taking one every frame is cheap
+#[derive(Clone)]
+pub struct WorldSnapshot {
+    tick: u64,
+    size: usize,
+    entities: EntityAllocator,
+    components: HashMap<TypeId, Arc<Any + Send + Sync>>,
+    resources: HashMap<TypeId, Option<Arc<Any + Send + Sync>>>
+}
+
+impl WorldSnapshot {
+
+    ///fails on archetype worlds, whose tables are not copied
+    pub fn capture(ecs: &ECS) -> Result<WorldSnapshot, &'static str> {
+        if ecs.archetypes.is_some() {
+            return Err(archetype::UNSUPPORTED);
+        }
+        Ok(WorldSnapshot{
+            tick: ecs.current_tick(),
+            size: ecs.size,
+            entities: ecs.entity_list.clone(),
+            components: ecs.storage.snapshot(),
+            resources: ecs.resources.snapshot()
+        })
+    }
+
+    ///put the ECS back to how it was when the snapshot was taken, no hooks run and no events are emitted
+    pub fn apply(&self, ecs: &mut ECS) -> Result<(), &'static str> {
+        if ecs.archetypes.is_some() {
+            return Err(archetype::UNSUPPORTED);
+        }
+        ecs.storage.set_tick(self.tick);
+        ecs.size = self.size;
+        ecs.entity_list = self.entities.clone();
+        ecs.storage.restore(&self.components);
+        ecs.resources.restore(&self.resources);
+        ecs.rebuild_network_index();
+        Ok(())
+    }
+
+    ///the world tick the snapshot was taken at
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    ///check whether both snapshots share the same copy of a component store
+    pub fn shares_components_with<T: 'static>(&self, other: &WorldSnapshot) -> bool {
+        match (self.components.get(&TypeId::of::<T>()), other.components.get(&TypeId::of::<T>())) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false
+        }
+    }
+}
+
+///the last few snapshots, the oldest is dropped once the ring is full, a ring with no capacity keeps nothing
+pub struct SnapshotRing {
+    snapshots: VecDeque<WorldSnapshot>,
+    capacity: usize
+}
+
+impl SnapshotRing {
+
+    pub fn new(capacity: usize) -> SnapshotRing {
+        SnapshotRing{ snapshots: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn push(&mut self, snapshot: WorldSnapshot) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.snapshots.len() >= self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    ///the snapshot taken at a tick, if it is still in the ring
+    pub fn get(&self, tick: u64) -> Option<&WorldSnapshot> {
+        self.snapshots.iter().find(|s| s.tick == tick)
+    }
+
+    pub fn latest(&self) -> Option<&WorldSnapshot> {
+        self.snapshots.back()
+    }
+
+    ///restore the snapshot taken at a tick and forget every snapshot taken after it, they are retaken while resimulating
+    ///like WorldSnapshot::apply no events are emitted, subscribers that mirror the world have to resync after a rollback
+    pub fn rollback(&mut self, ecs: &mut ECS, tick: u64) -> Result<(), &'static str> {
+        match self.get(tick) {
+            Some(snapshot) => snapshot.apply(ecs)?,
+            None => return Err("no snapshot for that tick")
+        }
+        self.snapshots.retain(|s| s.tick <= tick);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
@@ -3,8 +3,78 @@ use ECS;
 use component::Iter;
 use component::Storage;
 use component::DenseComponentStorage;
+use component::ComponentEntry;
+use event::EntityEvent;
+use event::ComponentEvent;
+use component::HookContext;
+use entity::EntityIndex;
+use entity::Entity;
+use entity::GENERATION_BITS;
+use entity::management::EntityAllocator;
+use entity::management::ReusePolicy;
+use lock::AccessError;
+use std::time::Duration;
+use std::thread;
+use fetch::Read;
+use fetch::Write;
+use fetch::ReadComp;
+use fetch::WriteComp;
+use fetch::FetchOne;
+use fetch::canonical_order;
+use resource::FromWorld;
+use std::rc::Rc;
+use std::cell::Cell;
+use std::any::TypeId;
+use crossbeam;
+#[cfg(feature = "serde")]
+use serialize::TypeRegistry;
+#[cfg(feature = "serde")]
+use serialize::Ron;
+#[cfg(feature = "serde")]
+use serialize::Cbor;
+#[cfg(feature = "serde")]
+use serialize::SerializeError;
+#[cfg(feature = "serde")]
+use entity::mapping::EntityMap;
+#[cfg(feature = "serde")]
+use entity::mapping::MapEntities;
+#[cfg(feature = "serde")]
+use prefab::PrefabError;
+#[cfg(feature = "serde")]
+use prefab::PrefabLibrary;
+#[cfg(feature = "serde")]
+use serialize::Json;
+use snapshot::SnapshotRing;
+#[cfg(feature = "serde")]
+use checksum::Divergence;
+#[cfg(feature = "serde")]
+use checksum::FnvHasher;
+#[cfg(feature = "serde")]
+use std::hash::Hasher;
+#[cfg(feature = "serde")]
+use delta::WorldDelta;
+#[cfg(feature = "serde")]
+use serialize::Format;
+#[cfg(feature = "serde")]
+use net::Server;
+#[cfg(feature = "serde")]
+use net::Client;
+#[cfg(feature = "serde")]
+use net::NetError;
+#[cfg(feature = "serde")]
+use net::link::MAX_PACKET;
+#[cfg(feature = "serde")]
+use net::link::LinkConditioner;
+#[cfg(feature = "serde")]
+use std::time::Instant;
+#[cfg(feature = "serde")]
+use net::id::NetworkId;
+use component::registry::ComponentRegistry;
+use archetype::Layout;
+use component::inline::InlineComponentStorage;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct StubComponentA {
     pub counter: u8
 }
@@ -16,7 +86,8 @@ impl Component for StubComponentA {
         self.counter += 1;
     }
 }
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct StubComponentB {
     pub counter: u8
 }
@@ -29,6 +100,11 @@ impl Component for StubComponentB{
     }
 }
 
+//the handle of the live entity at an index
+fn live(ecs: &ECS, index: usize) -> EntityIndex {
+    ecs.entity_list.entity(index).expect("no live entity at index")
+}
+
 #[test]
 fn initialise_ecs(){
     let entity_manager:ECS = ECS::new();
@@ -40,9 +116,9 @@ fn initialise_ecs(){
 #[test]
 fn create_new_empty_entity(){
     let mut entity_manager: ECS = ECS::new();
-    let (entity, generation) = entity_manager.allocate_new_entity();
-    assert_eq!(generation, 0);
-    assert_eq!(entity, 0);
+    let entity = entity_manager.allocate_new_entity();
+    assert_eq!(entity.generation(), 0);
+    assert_eq!(entity.index(), 0);
     assert_eq!(entity_manager.size, 1);
 }
 
@@ -51,9 +127,9 @@ fn add_component_to_entity(){
     let mut entity_manager: ECS = ECS::new();
     let index = entity_manager.allocate_new_entity();
     let _ok = entity_manager.register_new_component::<StubComponentA>().is_ok();
-    let (index1, gen) = entity_manager.add_component(index, StubComponentA {counter:0}).unwrap();
-    assert_eq!(gen, 0);
-    assert_eq!(index1, 0);
+    let index1 = entity_manager.add_component(index, StubComponentA {counter:0}).unwrap();
+    assert_eq!(index1.generation(), 0);
+    assert_eq!(index1.index(), 0);
 }
 
 #[test]
@@ -62,9 +138,9 @@ fn remove_component_from_entity(){
     let index = entity_manager.allocate_new_entity();
     let _ok = entity_manager.register_new_component::<StubComponentA>().is_ok();
     let index = entity_manager.add_component(index, StubComponentA {counter:0}).unwrap();
-    let (ind, gen) = entity_manager.remove_component::<StubComponentA>(index).unwrap();
-    assert_eq!(gen, 0);
-    assert_eq!(ind, 0);
+    let ind = entity_manager.remove_component::<StubComponentA>(index).unwrap();
+    assert_eq!(ind.generation(), 0);
+    assert_eq!(ind.index(), 0);
 }
 
 #[test]
@@ -93,9 +169,9 @@ fn get_component_iterator(){
     entity_manager.allocate_new_entity();
     entity_manager.allocate_new_entity();
     entity_manager.register_new_component::<StubComponentA>().expect("unable to register new component");
-    entity_manager.add_component((0,0), StubComponentA{ counter: 0 }).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentA{ counter: 1 }).expect("not registered");
-    entity_manager.add_component((2,0), StubComponentA{ counter: 2 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 0), StubComponentA{ counter: 0 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentA{ counter: 1 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 2), StubComponentA{ counter: 2 }).expect("not registered");
     let mut handle = entity_manager.get_component_write_handle::<StubComponentA>();
     let mut it = handle.get_mut_iter();
     assert_eq!(it.index(), 0)
@@ -108,11 +184,11 @@ fn get_component_test(){
     entity_manager.allocate_new_entity();
     entity_manager.allocate_new_entity();
     entity_manager.register_new_component::<StubComponentA>().expect("unable to register new component");
-    entity_manager.add_component((0,0), StubComponentA{ counter: 0 }).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentA{ counter: 1 }).expect("not registered");
-    entity_manager.add_component((2,0), StubComponentA{ counter: 2 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 0), StubComponentA{ counter: 0 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentA{ counter: 1 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 2), StubComponentA{ counter: 2 }).expect("not registered");
     {
-        let comp = entity_manager.get_mut::<StubComponentA>();
+        let comp = entity_manager.get_mut::<StubComponentA>().unwrap();
         let mut it = comp.get_mut_iter();
         loop{
             if let Some(x) = it.next_element(None) {
@@ -139,10 +215,10 @@ fn iterator_test_singular(){
     entity_manager.allocate_new_entity();
     entity_manager.register_new_component::<StubComponentA>().expect("unable to register new component");
     entity_manager.register_new_component::<StubComponentB>().expect("unable to register new component");
-    entity_manager.add_component((0,0), StubComponentA{ counter: 0 }).expect("not registered");
-    entity_manager.add_component((2,0), StubComponentA{counter: 0}).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentA{counter: 0}).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentB{counter: 100}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 0), StubComponentA{ counter: 0 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 2), StubComponentA{counter: 0}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentA{counter: 0}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentB{counter: 100}).expect("not registered");
     let mut handle = entity_manager.get_component_write_handle::<StubComponentB>();
     let mut itb = handle.get_mut_iter();
     let result = itb.next_element(None);
@@ -160,10 +236,10 @@ fn iterator_join_test(){
     entity_manager.allocate_new_entity();
     entity_manager.register_new_component::<StubComponentA>().expect("unable to register new component");
     entity_manager.register_new_component::<StubComponentB>().expect("unable to register new component");
-    entity_manager.add_component((0,0), StubComponentA{ counter: 0 }).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentA{counter: 0}).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentB{counter: 100}).expect("not registered");
-    entity_manager.add_component((2,0), StubComponentA{counter: 0}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 0), StubComponentA{ counter: 0 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentA{counter: 0}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentB{counter: 100}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 2), StubComponentA{counter: 0}).expect("not registered");
     let mut compha = entity_manager.get_component_write_handle::<StubComponentA>();
     let mut comphb = entity_manager.get_component_write_handle::<StubComponentB>();
     let ita = compha.get_mut_iter();
@@ -185,10 +261,10 @@ fn iterator_vec_test(){
     entity_manager.allocate_new_entity();
     entity_manager.register_new_component::<StubComponentA>().expect("unable to register new component");
     entity_manager.register_new_component::<StubComponentB>().expect("unable to register new component");
-    entity_manager.add_component((0,0), StubComponentA{ counter: 0 }).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentA{counter: 0}).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentB{counter: 100}).expect("not registered");
-    entity_manager.add_component((2,0), StubComponentA{counter: 0}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 0), StubComponentA{ counter: 0 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentA{counter: 0}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentB{counter: 100}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 2), StubComponentA{counter: 0}).expect("not registered");
     let mut compha = entity_manager.get_component_write_handle::<StubComponentA>();
     let ita = compha.get_mut_iter();
     let jit = ita.into_iterator_wrapper();
@@ -204,12 +280,12 @@ fn iterator_joint_vec_test(){
     entity_manager.allocate_new_entity();
     entity_manager.register_new_component::<StubComponentA>().expect("unable to register new component");
     entity_manager.register_new_component::<StubComponentB>().expect("unable to register new component");
-    entity_manager.add_component((0,0), StubComponentA{ counter: 0 }).expect("not registered");
-    entity_manager.add_component((0,0), StubComponentB{ counter: 0 }).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentA{counter: 0}).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentB{counter: 100}).expect("not registered");
-    entity_manager.add_component((2,0), StubComponentA{counter: 0}).expect("not registered");
-    entity_manager.add_component((2,0), StubComponentB{counter: 0}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 0), StubComponentA{ counter: 0 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 0), StubComponentB{ counter: 0 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentA{counter: 0}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentB{counter: 100}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 2), StubComponentA{counter: 0}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 2), StubComponentB{counter: 0}).expect("not registered");
     let mut compha = entity_manager.get_component_write_handle::<StubComponentA>();
     let mut comphb = entity_manager.get_component_write_handle::<StubComponentB>();
     let ita = compha.get_mut_iter();
@@ -229,10 +305,10 @@ fn iterator_joint_uneven_vec_test(){
     entity_manager.allocate_new_entity();
     entity_manager.register_new_component::<StubComponentA>().expect("unable to register new component");
     entity_manager.register_new_component::<StubComponentB>().expect("unable to register new component");
-    entity_manager.add_component((0,0), StubComponentA{ counter: 0 }).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentA{counter: 0}).expect("not registered");
-    entity_manager.add_component((1,0), StubComponentB{counter: 100}).expect("not registered");
-    entity_manager.add_component((2,0), StubComponentA{counter: 0}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 0), StubComponentA{ counter: 0 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentA{counter: 0}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentB{counter: 100}).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 2), StubComponentA{counter: 0}).expect("not registered");
     let mut compha = entity_manager.get_component_write_handle::<StubComponentA>();
     let mut comphb = entity_manager.get_component_write_handle::<StubComponentB>();
     let ita = compha.get_mut_iter();
@@ -251,10 +327,10 @@ fn entity_iterator_test() {
     entity_manager.allocate_new_entity();
     entity_manager.register_new_component::<StubComponentA>().expect("unable to register new component");
     entity_manager.register_new_component::<StubComponentB>().expect("unable to register new component");
-    entity_manager.add_component((0, 0), StubComponentA { counter: 0 }).expect("not registered");
-    entity_manager.add_component((1, 0), StubComponentA { counter: 0 }).expect("not registered");
-    entity_manager.add_component((1, 0), StubComponentB { counter: 100 }).expect("not registered");
-    entity_manager.add_component((2, 0), StubComponentA { counter: 0 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 0), StubComponentA { counter: 0 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentA { counter: 0 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 1), StubComponentB { counter: 100 }).expect("not registered");
+    entity_manager.add_component(live(&entity_manager, 2), StubComponentA { counter: 0 }).expect("not registered");
     let ent_it = entity_manager.get_entity_iterator_live();
     let mut compha = entity_manager.get_component_write_handle::<StubComponentA>();
     let ita = compha.get_mut_iter();
@@ -332,3 +408,1489 @@ fn ecs_get_mut_resource(){
     assert_eq!(45.0, resource_handle.r.0)
 }
 
+
+#[test]
+fn entity_events_test(){
+    let mut ecs = ECS::new();
+    let events = ecs.subscribe_entity_events();
+    let entity = ecs.allocate_new_entity();
+    ecs.deallocate_entity(entity).expect("unable to deallocate entity");
+    let result = events.try_iter().collect::<Vec<_>>();
+    assert_eq!(result, vec![EntityEvent::Allocated(entity), EntityEvent::Deallocated(entity)]);
+}
+
+#[test]
+fn component_events_test(){
+    let mut ecs = ECS::new();
+    let entity1 = ecs.allocate_new_entity();
+    let entity2 = ecs.allocate_new_entity();
+    ecs.register_new_component::<StubComponentA>().expect("unable to register new component");
+    ecs.register_new_component::<StubComponentB>().expect("unable to register new component");
+    let events = ecs.subscribe_component_events::<StubComponentA>().unwrap();
+    ecs.add_component(entity1, StubComponentA{ counter: 0 }).expect("not registered");
+    ecs.add_component(entity1, StubComponentA{ counter: 1 }).expect("not registered");
+    ecs.add_component(entity2, StubComponentB{ counter: 0 }).expect("not registered");
+    ecs.remove_component::<StubComponentA>(entity1).expect("unable to remove component");
+    ecs.add_component(entity2, StubComponentA{ counter: 0 }).expect("not registered");
+    ecs.deallocate_entity(entity2).expect("unable to deallocate entity");
+    let result = events.try_iter().collect::<Vec<_>>();
+    assert_eq!(result, vec![
+        ComponentEvent::Inserted(entity1),
+        ComponentEvent::Modified(entity1),
+        ComponentEvent::Removed(entity1),
+        ComponentEvent::Inserted(entity2),
+        ComponentEvent::Removed(entity2)
+    ]);
+}
+
+#[test]
+fn change_detection_test(){
+    let mut ecs = ECS::new();
+    let entity1 = ecs.allocate_new_entity();
+    let entity2 = ecs.allocate_new_entity();
+    ecs.register_new_component::<StubComponentA>().expect("unable to register new component");
+    ecs.register_new_component::<StubComponentB>().expect("unable to register new component");
+    ecs.add_component(entity1, StubComponentA{ counter: 0 }).expect("not registered");
+    ecs.add_component(entity2, StubComponentA{ counter: 0 }).expect("not registered");
+    ecs.add_component(entity2, StubComponentB{ counter: 0 }).expect("not registered");
+    let last_run = ecs.current_tick();
+    ecs.advance_tick();
+    {
+        let mut handle = ecs.get_component_write_handle::<StubComponentB>();
+        let mut it = handle.get_mut_iter();
+        while let Some((b, _)) = it.next_element(None) {
+            b.counter += 1;
+        }
+    }
+    let ha = ecs.get_component_read_handle::<StubComponentA>();
+    let hb = ecs.get_component_read_handle::<StubComponentB>();
+    assert_eq!(ha.get_changed_iterator(last_run).into_iterator_wrapper().count(), 0);
+    assert_eq!(ha.get_added_iterator(0).into_iterator_wrapper().count(), 2);
+    assert_eq!(hb.get_added_iterator(last_run).into_iterator_wrapper().count(), 0);
+    let changed = ha.get_iterator().join(hb.get_changed_iterator(last_run)).into_iterator_wrapper().collect::<Vec<_>>();
+    assert_eq!(changed.len(), 1);
+    assert_eq!((changed[0].1).counter, 1);
+}
+
+#[test]
+fn replace_keeps_added_test(){
+    let mut ecs = ECS::new();
+    let entity = ecs.allocate_new_entity();
+    ecs.register_new_component::<StubComponentA>().expect("unable to register new component");
+    ecs.register_new_component::<InlinePosition>().expect("unable to register new component");
+    ecs.add_component(entity, StubComponentA{ counter: 0 }).expect("not registered");
+    ecs.add_component(entity, InlinePosition{x: 0.0, y: 0.0}).expect("not registered");
+    let last_run = ecs.current_tick();
+    ecs.advance_tick();
+    ecs.add_component(entity, StubComponentA{ counter: 3 }).expect("not registered");
+    ecs.add_component(entity, InlinePosition{x: 3.0, y: 0.0}).expect("not registered");
+    let ha = ecs.get_component_read_handle::<StubComponentA>();
+    assert_eq!(ha.get_added_iterator(last_run).into_iterator_wrapper().count(), 0);
+    assert_eq!(ha.get_changed_iterator(last_run).into_iterator_wrapper().map(|a| a.counter).collect::<Vec<_>>(), vec![3]);
+    let hp = ecs.get_component_read_handle::<InlinePosition>();
+    assert_eq!(hp.get_added_iterator(last_run).into_iterator_wrapper().count(), 0);
+    assert_eq!(hp.get_changed_iterator(last_run).into_iterator_wrapper().count(), 1);
+}
+
+#[derive(Debug)]
+struct OpenHandles(u32);
+
+#[derive(Clone)]
+struct StubHandle;
+
+impl Component for StubHandle {
+    type ComponentStorage = DenseComponentStorage<Self>;
+
+    fn update(&mut self) {}
+
+    fn on_add(&mut self, _entity: EntityIndex, ctx: &mut HookContext) {
+        ctx.defer(|ecs| ecs.get_mut_resource::<OpenHandles>().unwrap().r.0 += 1);
+    }
+
+    fn on_remove(&mut self, _entity: EntityIndex, ctx: &mut HookContext) {
+        ctx.defer(|ecs| ecs.get_mut_resource::<OpenHandles>().unwrap().r.0 -= 1);
+    }
+}
+
+#[test]
+fn component_hooks_test(){
+    let mut ecs = ECS::new();
+    ecs.insert_new_resource(OpenHandles(0));
+    ecs.register_new_component::<StubHandle>().expect("unable to register new component");
+    let entity1 = ecs.allocate_new_entity();
+    let entity2 = ecs.allocate_new_entity();
+    ecs.add_component(entity1, StubHandle).expect("not registered");
+    ecs.add_component(entity2, StubHandle).expect("not registered");
+    ecs.add_component(entity2, StubHandle).expect("not registered");
+    assert_eq!(ecs.get_resource::<OpenHandles>().unwrap().r.0, 2);
+    ecs.remove_component::<StubHandle>(entity1).expect("unable to remove component");
+    assert_eq!(ecs.get_resource::<OpenHandles>().unwrap().r.0, 1);
+    ecs.deallocate_entity(entity2).expect("unable to deallocate entity");
+    assert_eq!(ecs.get_resource::<OpenHandles>().unwrap().r.0, 0);
+}
+
+#[test]
+fn update_components_test(){
+    let mut ecs = ECS::new();
+    let entity1 = ecs.allocate_new_entity();
+    let entity2 = ecs.allocate_new_entity();
+    ecs.register_new_component::<StubComponentA>().expect("unable to register new component");
+    ecs.register_new_component::<StubComponentB>().expect("unable to register new component");
+    ecs.add_component(entity1, StubComponentA{ counter: 0 }).expect("not registered");
+    ecs.add_component(entity2, StubComponentA{ counter: 10 }).expect("not registered");
+    ecs.add_component(entity2, StubComponentB{ counter: 0 }).expect("not registered");
+    ecs.update_components::<StubComponentA>().expect("unregistered component");
+    ecs.update_all().unwrap();
+    ecs.update_all_parallel().unwrap();
+    let ha = ecs.get_component_read_handle::<StubComponentA>();
+    let hb = ecs.get_component_read_handle::<StubComponentB>();
+    let a = ha.get_iterator().into_iterator_wrapper().map(|c| c.counter).collect::<Vec<_>>();
+    let b = hb.get_iterator().into_iterator_wrapper().map(|c| c.counter).collect::<Vec<_>>();
+    assert_eq!(a, vec![3, 13]);
+    assert_eq!(b, vec![2]);
+}
+
+#[test]
+fn try_handle_acquisition_test(){
+    let mut ecs = ECS::new();
+    ecs.register_new_component::<StubComponentA>().expect("unable to register new component");
+    ecs.insert_new_resource(DeltaTime(1.0));
+    {
+        let _writer = ecs.get_component_write_handle::<StubComponentA>();
+        assert!(ecs.try_get_component_write_handle::<StubComponentA>().is_err());
+        match ecs.get_component_read_handle_timeout::<StubComponentA>(Duration::from_millis(5)) {
+            Err(AccessError::Busy(name, _)) => assert!(name.ends_with("StubComponentA")),
+            _ => panic!("expected the component to be busy")
+        }
+        let _reader = ecs.get_resource::<DeltaTime>().unwrap();
+        assert!(ecs.try_get_resource::<DeltaTime>().is_ok());
+        assert!(ecs.try_get_mut_resource::<DeltaTime>().is_err());
+    }
+    assert!(ecs.try_get_component_write_handle::<StubComponentA>().is_ok());
+    assert!(ecs.get_mut_resource_timeout::<DeltaTime>(Duration::from_millis(5)).is_ok());
+    let missing = ecs.try_get_resource::<OpenHandles>();
+    match missing {
+        Err(AccessError::Missing(_)) => {},
+        _ => panic!("expected the resource to be missing")
+    }
+}
+
+#[test]
+fn poisoned_lock_recovery_test(){
+    let mut ecs = ECS::new();
+    let entity = ecs.allocate_new_entity();
+    ecs.register_new_component::<StubComponentA>().expect("unable to register new component");
+    ecs.add_component(entity, StubComponentA{ counter: 1 }).expect("not registered");
+    let storage = &ecs.storage;
+    let result = thread::scope(|scope| {
+        scope.spawn(|| {
+            let _handle = storage.get::<StubComponentA>().unwrap().write_handle();
+            panic!("poison the component lock");
+        }).join()
+    });
+    assert!(result.is_err());
+    assert_eq!(ecs.get_component_read_handle::<StubComponentA>().get_iterator().into_iterator_wrapper().count(), 1);
+    assert!(ecs.try_get_component_write_handle::<StubComponentA>().is_ok());
+}
+
+#[test]
+fn blocked_lock_waits_test(){
+    let mut ecs = ECS::new();
+    let entity = ecs.allocate_new_entity();
+    ecs.register_new_component::<StubComponentA>().expect("unable to register new component");
+    ecs.add_component(entity, StubComponentA{ counter: 1 }).expect("not registered");
+    let (held, acquired) = ::std::sync::mpsc::channel();
+    let ecs = &ecs;
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let mut writer = ecs.get_component_write_handle::<StubComponentA>();
+            held.send(()).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            writer.get_mut(entity).unwrap().counter = 2;
+        });
+        acquired.recv().unwrap();
+        //blocks until the writer is done rather than failing
+        let reader = ecs.get_component_read_handle::<StubComponentA>();
+        assert_eq!(reader.get_component(entity).unwrap().counter, 2);
+    });
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn borrow_tracker_report_test(){
+    let mut ecs = ECS::new();
+    ecs.register_new_component::<StubComponentA>().expect("unable to register new component");
+    let _writer = ecs.get_component_write_handle::<StubComponentA>();
+    let busy = ecs.try_get_component_read_handle::<StubComponentA>();
+    match busy {
+        Err(AccessError::Busy(_, holders)) => {
+            assert_eq!(holders.len(), 1);
+            assert_eq!(holders[0].thread, thread::current().id());
+            assert!(holders[0].location.file().ends_with("tests/mod.rs"));
+        },
+        _ => panic!("expected the component to be busy")
+    }
+    let report = ecs.storage.get::<StubComponentA>().unwrap().borrow_report();
+    assert!(report.contains("StubComponentA write-locked at"));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "deadlock")]
+fn borrow_tracker_self_deadlock_test(){
+    let mut ecs = ECS::new();
+    ecs.insert_new_resource(DeltaTime(1.0));
+    let _reader = ecs.get_resource::<DeltaTime>().unwrap();
+    let _writer = ecs.get_mut_resource::<DeltaTime>();
+}
+
+#[test]
+fn handle_deref_test(){
+    let mut ecs = ECS::new();
+    let entity1 = ecs.allocate_new_entity();
+    let entity2 = ecs.allocate_new_entity();
+    ecs.register_new_component::<StubComponentA>().expect("unable to register new component");
+    ecs.add_component(entity1, StubComponentA{ counter: 0 }).expect("not registered");
+    ecs.insert_new_resource(DeltaTime(1.0));
+    let events = ecs.subscribe_component_events::<StubComponentA>().unwrap();
+    {
+        let mut handle = ecs.get_component_write_handle::<StubComponentA>();
+        handle.get_mut(entity1).unwrap().counter += 5;
+        assert!(handle.get_mut(entity2).is_none());
+        handle.insert(entity2, StubComponentA{ counter: 7 });
+        assert!(handle.remove(entity1));
+        assert!(!handle.remove(entity1));
+        assert_eq!(handle.len(), 2);
+    }
+    {
+        let mut time = ecs.get_mut_resource::<DeltaTime>().unwrap();
+        time.0 += 1.0;
+    }
+    assert_eq!(ecs.get_resource::<DeltaTime>().unwrap().0, 2.0);
+    let handle = ecs.get_component_read_handle::<StubComponentA>();
+    assert_eq!(handle.len(), 2);
+    assert_eq!(handle.get_iterator().into_iterator_wrapper().map(|c| c.counter).collect::<Vec<_>>(), vec![7]);
+    assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![
+        ComponentEvent::Modified(entity1),
+        ComponentEvent::Inserted(entity2),
+        ComponentEvent::Removed(entity1)
+    ]);
+}
+
+#[test]
+fn fetch_test(){
+    let mut ecs = ECS::new();
+    let entity = ecs.allocate_new_entity();
+    ecs.register_new_component::<StubComponentA>().expect("unable to register new component");
+    ecs.register_new_component::<StubComponentB>().expect("unable to register new component");
+    ecs.add_component(entity, StubComponentA{ counter: 3 }).expect("not registered");
+    ecs.add_component(entity, StubComponentB{ counter: 0 }).expect("not registered");
+    ecs.insert_new_resource(DeltaTime(2.0));
+    ecs.insert_new_resource(OpenHandles(0));
+    {
+        let (time, mut handles, a, mut b) = ecs.fetch::<(Read<DeltaTime>, Write<OpenHandles>, ReadComp<StubComponentA>, WriteComp<StubComponentB>)>().unwrap();
+        handles.0 += 1;
+        let joint = a.get_iterator().join(b.get_mut_iter()).into_iterator_wrapper();
+        for (a, b) in joint {
+            b.counter = a.counter * time.0 as u8;
+        }
+    }
+    assert_eq!(ecs.get_resource::<OpenHandles>().unwrap().0, 1);
+    assert_eq!(ecs.get_component_read_handle::<StubComponentB>().get_iterator().into_iterator_wrapper().next().unwrap().counter, 6);
+    let conflict = ecs.fetch::<(ReadComp<StubComponentA>, WriteComp<StubComponentA>)>();
+    match conflict {
+        Err(AccessError::Conflict(_)) => {},
+        _ => panic!("expected a conflict")
+    }
+    let missing = ecs.fetch::<(ReadComp<StubComponentA>, Read<StubHandle>)>();
+    match missing {
+        Err(AccessError::Missing(_)) => {},
+        _ => panic!("expected a missing resource")
+    }
+}
+
+#[test]
+fn fetch_canonical_order_test(){
+    let forward = [ReadComp::<StubComponentA>::key(), WriteComp::<StubComponentB>::key(), Read::<DeltaTime>::key()];
+    let backward = [Read::<DeltaTime>::key(), WriteComp::<StubComponentB>::key(), ReadComp::<StubComponentA>::key()];
+    let forward_order = canonical_order(&forward).unwrap().into_iter().map(|i| forward[i].0).collect::<Vec<_>>();
+    let backward_order = canonical_order(&backward).unwrap().into_iter().map(|i| backward[i].0).collect::<Vec<_>>();
+    assert_eq!(forward_order, backward_order);
+}
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Score(u32);
+
+struct EntityCount(usize);
+
+impl FromWorld for EntityCount {
+    fn from_world(ecs: &ECS) -> Self {
+        EntityCount(ecs.get_entity_iterator_live().into_iterator_wrapper().count() + ecs.get_resource::<Score>().unwrap().0 as usize)
+    }
+}
+
+#[test]
+fn resource_initialisation_test(){
+    let mut ecs = ECS::new();
+    ecs.allocate_new_entity();
+    ecs.allocate_new_entity();
+    ecs.init_resource::<Score>();
+    ecs.get_mut_resource::<Score>().unwrap().0 = 10;
+    ecs.init_resource::<Score>();
+    assert_eq!(ecs.get_resource::<Score>().unwrap().0, 10);
+    ecs.init_resource::<EntityCount>();
+    assert_eq!(ecs.get_resource::<EntityCount>().unwrap().0, 12);
+    ecs.get_or_insert_resource_with(|| DeltaTime(1.0)).0 += 1.0;
+    ecs.get_or_insert_resource_with(|| DeltaTime(5.0)).0 += 1.0;
+    assert_eq!(ecs.get_resource::<DeltaTime>().unwrap().0, 3.0);
+    assert!(ecs.try_insert_resource(Score(0)).is_err());
+    assert_eq!(ecs.get_resource::<Score>().unwrap().0, 10);
+    assert!(ecs.try_insert_resource(OpenHandles(0)).is_ok());
+}
+
+#[test]
+fn non_send_resource_test(){
+    let mut ecs = ECS::new();
+    ecs.insert_non_send_resource(Rc::new(Cell::new(1))).unwrap();
+    assert!(ecs.is_main_thread());
+    assert!(ecs.resources.has_non_send_resources());
+    assert!(ecs.resources.contains_resource::<Rc<Cell<i32>>>());
+    ecs.get_non_send_resource_mut::<Rc<Cell<i32>>>().unwrap().set(2);
+    {
+        let held = ecs.get_non_send_resource::<Rc<Cell<i32>>>().unwrap();
+        assert_eq!(held.get(), 2);
+        match ecs.get_non_send_resource_mut::<Rc<Cell<i32>>>() {
+            Err(AccessError::Busy(_, _)) => {},
+            _ => panic!("non send resource was borrowed mutably twice")
+        }
+    }
+    let world = &ecs;
+    crossbeam::scope(|scope| {
+        scope.spawn(move |_| {
+            assert!(!world.is_main_thread());
+            match world.get_non_send_resource::<Rc<Cell<i32>>>() {
+                Err(AccessError::WrongThread(_)) => {},
+                _ => panic!("non send resource was reachable from another thread")
+            }
+        });
+    }).unwrap();
+    assert_eq!(ecs.remove_non_send_resource::<Rc<Cell<i32>>>().unwrap().get(), 2);
+    assert!(ecs.get_non_send_resource::<Rc<Cell<i32>>>().is_err());
+    assert!(!ecs.resources.contains_resource::<Rc<Cell<i32>>>());
+}
+
+#[cfg(feature = "serde")]
+fn save_registry() -> TypeRegistry {
+    let mut registry = TypeRegistry::new();
+    registry.register_component::<StubComponentA>("stub_a").unwrap();
+    registry.register_component::<StubComponentB>("stub_b").unwrap();
+    registry.register_resource::<Score>("score").unwrap();
+    registry
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn world_serialization_test(){
+    let registry = save_registry();
+    assert_eq!(registry.name_of::<StubComponentB>(), Some("stub_b"));
+    let mut ecs = ECS::new();
+    ecs.register_new_component::<StubComponentA>().unwrap();
+    ecs.register_new_component::<StubComponentB>().unwrap();
+    let first = ecs.allocate_new_entity();
+    let second = ecs.allocate_new_entity();
+    let third = ecs.allocate_new_entity();
+    ecs.add_component(first, StubComponentA{counter: 1}).unwrap();
+    ecs.add_component(second, StubComponentB{counter: 2}).unwrap();
+    ecs.add_component(third, StubComponentA{counter: 3}).unwrap();
+    ecs.deallocate_entity(second).unwrap();
+    ecs.insert_new_resource(Score(7));
+    ecs.insert_new_resource(DeltaTime(0.5));
+
+    let text = ecs.save(&registry, &Ron).unwrap();
+    let binary = ecs.save(&registry, &Cbor).unwrap();
+    assert!(binary.len() < text.len());
+    for loaded in vec![ECS::load(&registry, &Ron, &text).unwrap(), ECS::load(&registry, &Cbor, &binary).unwrap()] {
+        assert_eq!(loaded.entity_list, ecs.entity_list);
+        assert_eq!(loaded.get_entity_iterator_live().into_iterator_wrapper().count(), 2);
+        let a = loaded.get_component_read_handle::<StubComponentA>();
+        assert_eq!(a.get_iterator().into_iterator_wrapper().map(|c| c.counter).collect::<Vec<_>>(), vec![1, 3]);
+        let b = loaded.get_component_read_handle::<StubComponentB>();
+        assert_eq!(b.get_iterator().into_iterator_wrapper().count(), 0);
+        assert_eq!(loaded.get_resource::<Score>().unwrap().0, 7);
+        assert!(loaded.get_resource::<DeltaTime>().is_err());
+    }
+
+    let mut unknown = TypeRegistry::new();
+    unknown.register_component::<StubComponentA>("stub_a").unwrap();
+    match ECS::load(&unknown, &Ron, &text) {
+        Err(SerializeError::Unregistered(name)) => assert_eq!(name, "stub_b"),
+        _ => panic!("loaded a world with an unregistered component")
+    }
+    assert_eq!(unknown.register_component::<StubComponentB>("stub_a"), Err(SerializeError::Duplicate("stub_a".to_string())));
+
+    //a free list that would hand out a live slot or one past the end is refused before anything is built
+    for free_list in vec![vec![first.index()], vec![7], vec![second.index(), second.index()]] {
+        let mut data = registry.save(&ecs).unwrap();
+        data.entities.free_list = free_list.into_iter().collect();
+        match registry.load(data) {
+            Err(SerializeError::Decode(_)) => {},
+            _ => panic!("loaded a world with a corrupt free list")
+        }
+    }
+
+    //as are components stored against an entity that is not live in the save
+    for dead in vec![second, Entity::DANGLING] {
+        let mut data = registry.save(&ecs).unwrap();
+        let stubs = data.components.get_mut("stub_a").unwrap();
+        let value = stubs[0].1.clone();
+        stubs.push((dead, value));
+        match registry.load(data) {
+            Err(SerializeError::Decode(_)) => {},
+            _ => panic!("loaded a component stored against a dead entity")
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Parent(EntityIndex);
+
+#[cfg(feature = "serde")]
+impl Component for Parent {
+    type ComponentStorage = DenseComponentStorage<Self>;
+}
+
+#[cfg(feature = "serde")]
+impl MapEntities for Parent {
+    fn map_entities(&mut self, map: &EntityMap) {
+        self.0.map_entities(map);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn merge_remaps_entities_test(){
+    let mut registry = save_registry();
+    registry.register_mapped_component::<Parent>("parent").unwrap();
+    let mut level = ECS::new();
+    level.register_new_component::<StubComponentA>().unwrap();
+    level.register_new_component::<Parent>().unwrap();
+    let root = level.allocate_new_entity();
+    let child = level.allocate_new_entity();
+    level.add_component(root, StubComponentA{counter: 1}).unwrap();
+    level.add_component(child, Parent(root)).unwrap();
+    level.insert_new_resource(Score(3));
+
+    let mut world = ECS::new();
+    world.insert_new_resource(Score(10));
+    let existing = world.allocate_new_entity();
+    let map = world.merge(&registry, &Ron, &level.save(&registry, &Ron).unwrap()).unwrap();
+    assert_eq!(map.len(), 2);
+    let new_root = map.get(root).unwrap();
+    let new_child = map.get(child).unwrap();
+    assert!(new_root != root && new_root != existing && new_child != child);
+    match world.get_component_read_handle::<Parent>().get(new_child) {
+        ComponentEntry::Entry(parent) => assert_eq!(parent.0, new_root),
+        ComponentEntry::Empty => panic!("merged entity lost its parent")
+    }
+    match world.get_component_read_handle::<StubComponentA>().get(new_root) {
+        ComponentEntry::Entry(a) => assert_eq!(a.counter, 1),
+        ComponentEntry::Empty => panic!("merged entity lost its component")
+    }
+    assert_eq!(world.get_resource::<Score>().unwrap().0, 10);
+
+    let again = world.merge_world(&registry, &level).unwrap();
+    assert!(again.get(child).unwrap() != new_child);
+    assert_eq!(world.get_entity_iterator_live().into_iterator_wrapper().count(), 5);
+
+    //a reference to an entity that is not part of the import must not alias the destination's entity with that handle
+    let gone = level.allocate_new_entity();
+    level.deallocate_entity(gone).unwrap();
+    let orphan = level.allocate_new_entity();
+    level.add_component(orphan, Parent(gone)).unwrap();
+    let orphaned = world.merge_world(&registry, &level).unwrap().get(orphan).unwrap();
+    match world.get_component_read_handle::<Parent>().get(orphaned) {
+        ComponentEntry::Entry(parent) => assert_eq!(parent.0, Entity::DANGLING),
+        ComponentEntry::Empty => panic!("merged entity lost its parent")
+    }
+    assert!(!world.entity_list.is_live(Entity::DANGLING));
+
+    //a save that does not decode is refused before any of its entities are allocated
+    let before = world.get_entity_iterator_live().into_iterator_wrapper().count();
+    let mut bad_component = registry.save(&level).unwrap();
+    bad_component.components.get_mut("parent").unwrap()[0].1 = ::serde_value::Value::String("not a parent".to_string());
+    let mut bad_resource = registry.save(&level).unwrap();
+    bad_resource.resources.insert("score".to_string(), ::serde_value::Value::String("not a score".to_string()));
+    for data in vec![bad_component, bad_resource] {
+        match registry.merge(&mut world, data) {
+            Err(SerializeError::Decode(_)) => {},
+            _ => panic!("merged a save that does not decode")
+        }
+        assert_eq!(world.get_entity_iterator_live().into_iterator_wrapper().count(), before);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Stats {
+    hp: u32,
+    speed: f32
+}
+
+#[cfg(feature = "serde")]
+impl Component for Stats {
+    type ComponentStorage = DenseComponentStorage<Self>;
+}
+
+#[cfg(feature = "serde")]
+const PREFABS: &str = r#"{
+    "monster": (components: {"stats": (hp: 10, speed: 1.5), "stub_a": (counter: 0)}),
+    "goblin": (base: Some("monster"), components: {"stats": (hp: 4)}),
+    "goblin_chief": (base: Some("goblin"), components: {"stub_a": (counter: 9)}),
+    "loop_a": (base: Some("loop_b")),
+    "loop_b": (base: Some("loop_a"))
+}"#;
+
+#[cfg(feature = "serde")]
+#[test]
+fn spawn_prefab_test(){
+    let mut registry = TypeRegistry::new();
+    registry.register_component::<Stats>("stats").unwrap();
+    registry.register_component::<StubComponentA>("stub_a").unwrap();
+    let mut prefabs = PrefabLibrary::new();
+    prefabs.load(&Ron, PREFABS.as_bytes()).unwrap();
+    prefabs.load(&Json, br#"{"slime": {"components": {"stats": {"hp": 2, "speed": 0.5}}}}"#).unwrap();
+
+    let mut ecs = ECS::new();
+    let goblin = ecs.spawn_prefab(&registry, &prefabs, "goblin").unwrap();
+    let chief = ecs.spawn_prefab(&registry, &prefabs, "goblin_chief").unwrap();
+    let slime = ecs.spawn_prefab(&registry, &prefabs, "slime").unwrap();
+    {
+        let stats = ecs.get_component_read_handle::<Stats>();
+        let stub = ecs.get_component_read_handle::<StubComponentA>();
+        match (stats.get(goblin), stats.get(chief), stats.get(slime)) {
+            (ComponentEntry::Entry(g), ComponentEntry::Entry(c), ComponentEntry::Entry(s)) => {
+                assert_eq!(**g, Stats{hp: 4, speed: 1.5});
+                assert_eq!(**c, Stats{hp: 4, speed: 1.5});
+                assert_eq!(**s, Stats{hp: 2, speed: 0.5});
+            },
+            _ => panic!("prefab did not add its stats")
+        }
+        match (stub.get(goblin), stub.get(chief), stub.get(slime)) {
+            (ComponentEntry::Entry(g), ComponentEntry::Entry(c), ComponentEntry::Empty) => {
+                assert_eq!(g.counter, 0);
+                assert_eq!(c.counter, 9);
+            },
+            _ => panic!("prefab components were not inherited")
+        }
+    }
+
+    assert_eq!(ecs.spawn_prefab(&registry, &prefabs, "loop_a"), Err(PrefabError::Cycle("loop_a".to_string())));
+    assert_eq!(ecs.spawn_prefab(&registry, &prefabs, "dragon"), Err(PrefabError::Unknown("dragon".to_string())));
+    prefabs.load(&Ron, br#"{"ghost": (components: {"stats": (hp: 1, speed: 1.0), "ethereal": ()})}"#).unwrap();
+    let live = ecs.get_entity_iterator_live().into_iterator_wrapper().count();
+    assert_eq!(ecs.spawn_prefab(&registry, &prefabs, "ghost"), Err(PrefabError::UnregisteredComponent("ethereal".to_string())));
+    prefabs.load(&Ron, br#"{"broken": (components: {"stats": (hp: "lots")})}"#).unwrap();
+    assert!(ecs.spawn_prefab(&registry, &prefabs, "broken").is_err());
+    assert_eq!(ecs.get_entity_iterator_live().into_iterator_wrapper().count(), live);
+
+    //the registry that saved a world keeps spawning prefabs into it after it is loaded
+    let mut loaded = ECS::load(&registry, &Ron, &ecs.save(&registry, &Ron).unwrap()).unwrap();
+    let spawned = loaded.spawn_prefab(&registry, &prefabs, "goblin_chief").unwrap();
+    assert_eq!(loaded.get_component_read_handle::<StubComponentA>().get_component(spawned).unwrap().counter, 9);
+}
+
+#[test]
+fn clone_entity_test(){
+    let mut ecs = ECS::new();
+    ecs.register_new_component::<StubComponentA>().unwrap();
+    ecs.register_new_component::<StubComponentB>().unwrap();
+    ecs.register_new_component::<StubHandle>().unwrap();
+    ecs.insert_new_resource(OpenHandles(0));
+    let source = ecs.allocate_new_entity();
+    ecs.add_component(source, StubComponentA{counter: 4}).unwrap();
+    ecs.add_component(source, StubHandle).unwrap();
+    let copy = ecs.clone_entity(source).unwrap();
+    assert!(copy != source);
+    assert_eq!(ecs.get_resource::<OpenHandles>().unwrap().0, 2);
+    ecs.get_mut::<StubComponentA>().unwrap().get_mut(copy).unwrap().counter = 8;
+    match (ecs.get_component_read_handle::<StubComponentA>().get(source), ecs.get_component_read_handle::<StubComponentA>().get(copy)) {
+        (ComponentEntry::Entry(a), ComponentEntry::Entry(b)) => assert_eq!((a.counter, b.counter), (4, 8)),
+        _ => panic!("component was not cloned")
+    }
+    match ecs.get_component_read_handle::<StubComponentB>().get(copy) {
+        ComponentEntry::Empty => {},
+        _ => panic!("clone gained a component the source did not have")
+    }
+    ecs.deallocate_entity(source).unwrap();
+    assert!(ecs.clone_entity(source).is_err());
+}
+
+#[test]
+fn snapshot_rollback_test(){
+    let mut ecs = ECS::new();
+    ecs.register_new_component::<StubComponentA>().unwrap();
+    ecs.register_new_component::<StubComponentB>().unwrap();
+    ecs.register_rollback_resource::<Score>();
+    ecs.insert_new_resource(Score(0));
+    ecs.insert_new_resource(DeltaTime(1.0));
+    let first = ecs.allocate_new_entity();
+    ecs.add_component(first, StubComponentA{counter: 0}).unwrap();
+    ecs.add_component(first, StubComponentB{counter: 0}).unwrap();
+
+    let mut ring = SnapshotRing::new(3);
+    for _ in 0 .. 5 {
+        ring.push(ecs.snapshot().unwrap());
+        ecs.update_components::<StubComponentA>().unwrap();
+        ecs.get_mut_resource::<Score>().unwrap().0 += 1;
+        ecs.advance_tick();
+    }
+    assert_eq!(ring.len(), 3);
+    assert!(ring.get(1).is_none());
+    let oldest = ring.get(3).unwrap();
+    let newest = ring.latest().unwrap();
+    assert!(newest.shares_components_with::<StubComponentB>(oldest));
+    assert!(!newest.shares_components_with::<StubComponentA>(oldest));
+
+    let spawned = ecs.allocate_new_entity();
+    ecs.add_component(spawned, StubComponentB{counter: 9}).unwrap();
+    ecs.get_mut_resource::<DeltaTime>().unwrap().0 = 2.0;
+    ring.rollback(&mut ecs, 4).unwrap();
+    assert_eq!(ring.len(), 2);
+    assert_eq!(ecs.current_tick(), 4);
+    assert_eq!(ecs.get_resource::<Score>().unwrap().0, 3);
+    assert_eq!(ecs.get_resource::<DeltaTime>().unwrap().0, 2.0);
+    assert_eq!(ecs.get_entity_iterator_live().into_iterator_wrapper().count(), 1);
+    match ecs.get_component_read_handle::<StubComponentA>().get(first) {
+        ComponentEntry::Entry(a) => assert_eq!(a.counter, 3),
+        ComponentEntry::Empty => panic!("component was lost on restore")
+    }
+    assert_eq!(ecs.get_component_read_handle::<StubComponentB>().get_iterator().into_iterator_wrapper().count(), 1);
+    assert!(ring.rollback(&mut ecs, 1).is_err());
+
+    let mut disabled = SnapshotRing::new(0);
+    disabled.push(ecs.snapshot().unwrap());
+    assert!(disabled.is_empty());
+    assert!(disabled.rollback(&mut ecs, 4).is_err());
+}
+
+#[cfg(feature = "serde")]
+fn lockstep_peer(register_b_first: bool) -> (ECS, TypeRegistry) {
+    let mut ecs = ECS::new();
+    let mut registry = save_registry();
+    if register_b_first {
+        ecs.register_new_component::<StubComponentB>().unwrap();
+        ecs.register_new_component::<StubComponentA>().unwrap();
+        registry.register_hashed_component::<StubComponentB>("stub_b").unwrap();
+        registry.register_hashed_component::<StubComponentA>("stub_a").unwrap();
+    }else{
+        ecs.register_new_component::<StubComponentA>().unwrap();
+        ecs.register_new_component::<StubComponentB>().unwrap();
+        registry.register_hashed_component::<StubComponentA>("stub_a").unwrap();
+        registry.register_hashed_component::<StubComponentB>("stub_b").unwrap();
+    }
+    for i in 0 .. 4 {
+        let entity = ecs.allocate_new_entity();
+        ecs.add_component(entity, StubComponentA{counter: i}).unwrap();
+        ecs.add_component(entity, StubComponentB{counter: i * 2}).unwrap();
+    }
+    (ecs, registry)
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn checksum_test(){
+    let (mut ours, mut registry) = lockstep_peer(false);
+    let (mut theirs, their_registry) = lockstep_peer(true);
+    assert_eq!(ours.checksum(&registry).unwrap(), theirs.checksum(&their_registry).unwrap());
+    ours.update_all().unwrap();
+    theirs.update_all().unwrap();
+    assert_eq!(ours.checksum(&registry).unwrap(), theirs.checksum(&their_registry).unwrap());
+    assert_eq!(ours.checksum_report(&registry).unwrap().first_divergence(&theirs.checksum_report(&their_registry).unwrap()), None);
+
+    let changed = live(&theirs, 2);
+    theirs.get_mut::<StubComponentB>().unwrap().get_mut(changed).unwrap().counter += 1;
+    assert!(ours.checksum(&registry).unwrap() != theirs.checksum(&their_registry).unwrap());
+    assert_eq!(ours.checksum_report(&registry).unwrap().first_divergence(&theirs.checksum_report(&their_registry).unwrap()), Some(Divergence::Component("stub_b".to_string())));
+
+    theirs.deallocate_entity(live(&theirs, 3)).unwrap();
+    assert_eq!(ours.checksum_report(&registry).unwrap().first_divergence(&theirs.checksum_report(&their_registry).unwrap()), Some(Divergence::Entities));
+    assert!(registry.register_hashed_component::<StubComponentA>("stub_a").is_err());
+    //saved as stub_a, so it can not be hashed under another name
+    assert_eq!(registry.register_hashed_component::<StubComponentA>("renamed"), Err(SerializeError::Duplicate("renamed".to_string())));
+
+    //the same registry loads a world and checks it still matches
+    let loaded = ECS::load(&registry, &Cbor, &ours.save(&registry, &Cbor).unwrap()).unwrap();
+    assert_eq!(loaded.checksum(&registry).unwrap(), ours.checksum(&registry).unwrap());
+
+    //integers hash as their little endian bytes whatever the machine
+    let mut bytes = FnvHasher::new();
+    bytes.write(&[1, 0, 0, 0, 0xfe, 0xff]);
+    let mut integers = FnvHasher::new();
+    integers.write_u32(1);
+    integers.write_i16(-2);
+    assert_eq!(integers.finish(), bytes.finish());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn world_delta_test(){
+    let registry = save_registry();
+    let mut server = ECS::new();
+    server.register_new_component::<StubComponentA>().unwrap();
+    server.register_new_component::<StubComponentB>().unwrap();
+    let mut entities = Vec::new();
+    for i in 0 .. 4 {
+        let entity = server.allocate_new_entity();
+        server.add_component(entity, StubComponentA{counter: i}).unwrap();
+        entities.push(entity);
+    }
+    let mut sent = registry.save(&server).unwrap();
+    let mut client = registry.load(sent.clone()).unwrap();
+    assert!(server.delta_since(&registry, &sent).unwrap().is_empty());
+
+    server.deallocate_entity(entities[0]).unwrap();
+    let reused = server.allocate_new_entity();
+    assert_eq!((reused.index(), reused.generation()), (0, 1));
+    server.add_component(reused, StubComponentB{counter: 5}).unwrap();
+    server.get_mut::<StubComponentA>().unwrap().get_mut(entities[1]).unwrap().counter = 50;
+    server.remove_component::<StubComponentA>(entities[2]).unwrap();
+    server.add_component(entities[3], StubComponentB{counter: 7}).unwrap();
+    let fresh = server.allocate_new_entity();
+    server.add_component(fresh, StubComponentA{counter: 9}).unwrap();
+
+    let delta = server.delta_since(&registry, &sent).unwrap();
+    assert_eq!(delta.despawned, vec![entities[0]]);
+    assert_eq!(delta.spawned, vec![reused, fresh]);
+    assert_eq!(delta.changed["stub_a"].len(), 1);
+    assert_eq!(delta.removed["stub_a"], vec![entities[2]]);
+    assert_eq!(delta.added["stub_b"].len(), 2);
+
+    let bytes = Cbor.encode(&delta).unwrap();
+    let received: WorldDelta = Cbor.decode(&bytes).unwrap();
+    client.apply_delta(&registry, &received).unwrap();
+    sent = registry.save(&server).unwrap();
+    let mirrored = registry.save(&client).unwrap();
+    assert_eq!(mirrored.components, sent.components);
+    assert_eq!(WorldDelta::between(&mirrored, &sent), WorldDelta::default());
+    assert_eq!(client.get_entity_iterator_live().into_iterator_wrapper().count(), 5);
+}
+
+#[cfg(feature = "serde")]
+fn replication_registry() -> TypeRegistry {
+    let mut registry = TypeRegistry::new();
+    registry.register_component::<StubComponentA>("stub_a").unwrap();
+    registry.register_mapped_component::<Parent>("parent").unwrap();
+    registry
+}
+
+//runs both sides until the client holds exactly the visible part of the server's replicated state, as seen through the client's entity map
+#[cfg(feature = "serde")]
+fn replicate_until_synced(server: &mut Server, server_ecs: &ECS, client: &mut Client, client_ecs: &mut ECS, visible: &Fn(&ECS, EntityIndex) -> bool) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        server.update(server_ecs).unwrap();
+        thread::sleep(Duration::from_millis(2));
+        client.update(client_ecs).unwrap();
+        let local = client_ecs.get_component_read_handle::<StubComponentA>();
+        let server_side = server_ecs.get_component_read_handle::<StubComponentA>();
+        let mut expected = 0;
+        let mut matched = 0;
+        for entity in server_ecs.entity_list.live() {
+            if let (true, ComponentEntry::Entry(a)) = (visible(server_ecs, entity), server_side.get(entity)) {
+                expected += 1;
+                match client.local_entity(entity).map(|l| local.get(l)) {
+                    Some(ComponentEntry::Entry(b)) if a.counter == b.counter => matched += 1,
+                    _ => {}
+                }
+            }
+        }
+        if matched == expected && local.get_iterator().into_iterator_wrapper().count() == expected {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(feature = "serde")]
+fn everything(_ecs: &ECS, _entity: EntityIndex) -> bool {
+    true
+}
+
+#[cfg(feature = "serde")]
+fn even_counters(ecs: &ECS, entity: EntityIndex) -> bool {
+    match ecs.get_component_read_handle::<StubComponentA>().get(entity) {
+        ComponentEntry::Entry(a) => a.counter % 2 == 0,
+        ComponentEntry::Empty => false
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn replication_test(){
+    let mut server_ecs = ECS::new();
+    server_ecs.register_new_component::<StubComponentA>().unwrap();
+    server_ecs.register_new_component::<Parent>().unwrap();
+    let root = server_ecs.allocate_new_entity();
+    server_ecs.add_component(root, StubComponentA{counter: 1}).unwrap();
+    let child = server_ecs.allocate_new_entity();
+    server_ecs.add_component(child, StubComponentA{counter: 2}).unwrap();
+    server_ecs.add_component(child, Parent(root)).unwrap();
+
+    let mut client_ecs = ECS::new();
+    client_ecs.allocate_new_entity();
+    let mut server = Server::bind("127.0.0.1:0", replication_registry(), LinkConditioner::perfect()).unwrap();
+    let mut client = Client::connect(server.local_addr().unwrap(), replication_registry(), LinkConditioner::perfect()).unwrap();
+    assert!(replicate_until_synced(&mut server, &server_ecs, &mut client, &mut client_ecs, &everything));
+    let local_root = client.local_entity(root).unwrap();
+    assert!(local_root != root);
+    match client_ecs.get_component_read_handle::<Parent>().get(client.local_entity(child).unwrap()) {
+        ComponentEntry::Entry(parent) => assert_eq!(parent.0, local_root),
+        ComponentEntry::Empty => panic!("parent was not replicated")
+    }
+
+    server_ecs.get_mut::<StubComponentA>().unwrap().get_mut(root).unwrap().counter = 10;
+    server_ecs.deallocate_entity(child).unwrap();
+    assert!(replicate_until_synced(&mut server, &server_ecs, &mut client, &mut client_ecs, &everything));
+    assert!(client.local_entity(child).is_none());
+    assert_eq!(client_ecs.get_entity_iterator_live().into_iterator_wrapper().count(), 2);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn replication_over_lossy_link_test(){
+    let mut server_ecs = ECS::new();
+    server_ecs.register_new_component::<StubComponentA>().unwrap();
+    for i in 0 .. 10 {
+        let entity = server_ecs.allocate_new_entity();
+        server_ecs.add_component(entity, StubComponentA{counter: i}).unwrap();
+    }
+    let lossy = LinkConditioner::new(0.3, Duration::from_millis(5), 7);
+    let mut server = Server::bind("127.0.0.1:0", replication_registry(), lossy).unwrap();
+    let mut client_ecs = ECS::new();
+    let mut client = Client::connect(server.local_addr().unwrap(), replication_registry(), lossy).unwrap();
+    assert!(replicate_until_synced(&mut server, &server_ecs, &mut client, &mut client_ecs, &everything));
+
+    let addr = server.clients()[0];
+    server.set_interest(addr, Box::new(even_counters)).unwrap();
+    for _ in 0 .. 3 {
+        server_ecs.update_components::<StubComponentA>().unwrap();
+        assert!(replicate_until_synced(&mut server, &server_ecs, &mut client, &mut client_ecs, &even_counters));
+        assert_eq!(client_ecs.get_entity_iterator_live().into_iterator_wrapper().count(), 5);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn server_client_failures_test(){
+    let mut server_ecs = ECS::new();
+    server_ecs.register_new_component::<StubComponentA>().unwrap();
+    let first = server_ecs.allocate_new_entity();
+    server_ecs.add_component(first, StubComponentA{counter: 0}).unwrap();
+    let mut server = Server::bind("127.0.0.1:0", replication_registry(), LinkConditioner::perfect()).unwrap();
+    let mut client_ecs = ECS::new();
+    let mut client = Client::connect(server.local_addr().unwrap(), replication_registry(), LinkConditioner::perfect()).unwrap();
+    assert!(replicate_until_synced(&mut server, &server_ecs, &mut client, &mut client_ecs, &everything));
+    //let the client's last ack arrive, the server hears from it on the next update and never again
+    thread::sleep(Duration::from_millis(20));
+
+    //a world too big for one datagram is refused for that client, not truncated, and the server keeps going
+    for _ in 0 .. 20000 {
+        let entity = server_ecs.allocate_new_entity();
+        server_ecs.add_component(entity, StubComponentA{counter: 1}).unwrap();
+    }
+    match server.update(&server_ecs) {
+        Err(NetError::Clients(failed)) => match failed[..] {
+            [(addr, NetError::TooLarge(size))] => {
+                assert_eq!(addr, server.clients()[0]);
+                assert!(size > MAX_PACKET);
+            },
+            _ => panic!("expected the one client to be refused an oversized state")
+        },
+        _ => panic!("oversized state was sent")
+    }
+    //it is only reported the first time
+    server.update(&server_ecs).unwrap();
+
+    //a client that stops acknowledging is dropped after the timeout
+    server.set_timeout(3);
+    let empty = ECS::new();
+    server.update(&empty).unwrap();
+    assert_eq!(server.clients().len(), 1);
+    server.update(&empty).unwrap();
+    assert!(server.clients().is_empty());
+
+    //and rejoins once it notices states have stopped arriving
+    let mut rejoined = ECS::new();
+    rejoined.register_new_component::<StubComponentA>().unwrap();
+    let entity = rejoined.allocate_new_entity();
+    rejoined.add_component(entity, StubComponentA{counter: 5}).unwrap();
+    assert!(replicate_until_synced(&mut server, &rejoined, &mut client, &mut client_ecs, &everything));
+    assert_eq!(server.clients().len(), 1);
+}
+
+#[test]
+fn clone_networked_entity_test(){
+    let mut ecs = ECS::new();
+    let (source, id) = ecs.spawn_networked();
+    let copy = ecs.clone_entity(source).unwrap();
+    let copy_id = ecs.network_id(copy).unwrap();
+    assert!(copy_id != id);
+    assert_eq!(ecs.network_id(source), Some(id));
+    assert_eq!(ecs.entity_for_network_id(id), Some(source));
+    assert_eq!(ecs.entity_for_network_id(copy_id), Some(copy));
+}
+
+#[test]
+fn network_id_test(){
+    let mut ecs = ECS::new();
+    let (a, id_a) = ecs.spawn_networked();
+    let (b, id_b) = ecs.spawn_networked();
+    assert!(id_a != id_b);
+    assert_eq!(ecs.entity_for_network_id(id_a), Some(a));
+    assert_eq!(ecs.network_id(b), Some(id_b));
+
+    ecs.deallocate_entity(a).unwrap();
+    assert_eq!(ecs.entity_for_network_id(id_a), None);
+    assert_eq!(ecs.network_id(a), None);
+    let (_, id_c) = ecs.spawn_networked();
+    assert!(id_c != id_a && id_c != id_b);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn network_id_replication_test(){
+    let mut ecs = ECS::new();
+    let (a, _) = ecs.spawn_networked();
+    let (b, id_b) = ecs.spawn_networked();
+    ecs.deallocate_entity(a).unwrap();
+    let (c, id_c) = ecs.spawn_networked();
+
+    let mut registry = TypeRegistry::new();
+    registry.register_component::<NetworkId>("network_id").unwrap();
+    let bytes = ecs.save(&registry, &Ron).unwrap();
+    let mut loaded = ECS::load(&registry, &Ron, &bytes).unwrap();
+    assert_eq!(loaded.entity_for_network_id(id_b), Some(b));
+    assert_eq!(loaded.entity_for_network_id(id_c), Some(c));
+    let (_, id_d) = loaded.spawn_networked();
+    assert!(id_d != id_b && id_d != id_c);
+
+    let mut server_ecs = ECS::new();
+    server_ecs.register_new_component::<StubComponentA>().unwrap();
+    let (entity, id) = server_ecs.spawn_networked();
+    server_ecs.add_component(entity, StubComponentA{counter: 3}).unwrap();
+    let mut client_ecs = ECS::new();
+    client_ecs.allocate_new_entity();
+    let mut replicated = replication_registry();
+    replicated.register_component::<NetworkId>("network_id").unwrap();
+    let mut server = Server::bind("127.0.0.1:0", replicated, LinkConditioner::perfect()).unwrap();
+    let mut replicated = replication_registry();
+    replicated.register_component::<NetworkId>("network_id").unwrap();
+    let mut client = Client::connect(server.local_addr().unwrap(), replicated, LinkConditioner::perfect()).unwrap();
+    assert!(replicate_until_synced(&mut server, &server_ecs, &mut client, &mut client_ecs, &everything));
+    assert_eq!(client_ecs.entity_for_network_id(id), client.local_entity(entity));
+    assert!(client_ecs.entity_for_network_id(id) != Some(entity));
+}
+
+#[test]
+fn move_entity_test(){
+    let registry = ComponentRegistry::new();
+    assert!(registry.is_empty());
+    let mut staging = ECS::with_registry(&registry);
+    staging.register_new_component::<StubComponentA>().unwrap();
+    assert!(!registry.is_empty());
+    let mut live = ECS::with_registry(&registry);
+    live.allocate_new_entity();
+    assert!(live.component_registry().shares_with(staging.component_registry()));
+    assert_eq!(live.get_component_read_handle::<StubComponentA>().get_iterator().into_iterator_wrapper().count(), 0);
+
+    staging.register_new_component::<StubComponentB>().unwrap();
+    live.sync_components();
+    let (entity, id) = staging.spawn_networked();
+    staging.add_component(entity, StubComponentA{counter: 4}).unwrap();
+    staging.add_component(entity, StubComponentB{counter: 5}).unwrap();
+
+    let moved = ECS::move_entity(&mut staging, &mut live, entity).unwrap();
+    assert!(moved != entity);
+    assert_eq!(staging.get_entity_iterator_live().into_iterator_wrapper().count(), 0);
+    match staging.get_component_read_handle::<StubComponentA>().get(entity) {
+        ComponentEntry::Entry(_) => panic!("component was left behind"),
+        ComponentEntry::Empty => {}
+    }
+    match live.get_component_read_handle::<StubComponentA>().get(moved) {
+        ComponentEntry::Entry(a) => assert_eq!(a.counter, 4),
+        ComponentEntry::Empty => panic!("component was not moved")
+    }
+    match live.get_component_read_handle::<StubComponentB>().get(moved) {
+        ComponentEntry::Entry(b) => assert_eq!(b.counter, 5),
+        ComponentEntry::Empty => panic!("component was not moved")
+    }
+    assert_eq!(staging.entity_for_network_id(id), None);
+    assert_eq!(live.entity_for_network_id(id), Some(moved));
+    let before = live.get_entity_iterator_live().into_iterator_wrapper().count();
+    assert!(ECS::move_entity(&mut staging, &mut live, entity).is_err());
+    assert_eq!(live.get_entity_iterator_live().into_iterator_wrapper().count(), before);
+
+    let mut ui = ECS::new();
+    let back = ECS::move_entity(&mut live, &mut ui, moved).unwrap();
+    assert!(!ui.component_registry().shares_with(&registry));
+    assert_eq!(ui.network_id(back), Some(id));
+
+    //a component a store refuses comes back intact so move_entity can return it to the source
+    let (ty, component) = ui.storage.take_entity(back).into_iter().find(|c| c.0 == TypeId::of::<StubComponentB>()).unwrap();
+    match ui.storage.insert_boxed(ty, Entity::DANGLING, component) {
+        Err((_, refused)) => assert_eq!(refused.downcast::<StubComponentB>().unwrap().counter, 5),
+        Ok(()) => panic!("store took a component for Entity::DANGLING")
+    }
+}
+
+#[test]
+fn archetype_layout_test(){
+    let mut ecs = ECS::with_layout(Layout::Archetype);
+    assert_eq!(ecs.layout(), Layout::Archetype);
+    assert_eq!(ECS::new().layout(), Layout::Dense);
+    let entities = (0..4).map(|_| ecs.allocate_new_entity()).collect::<Vec<_>>();
+    for (i, entity) in entities.iter().enumerate() {
+        ecs.add_component(*entity, StubComponentA{counter: i as u8}).unwrap();
+    }
+    ecs.add_component(entities[1], StubComponentB{counter: 10}).unwrap();
+    ecs.add_component(entities[3], StubComponentB{counter: 30}).unwrap();
+    {
+        let archetypes = ecs.archetypes.as_ref().unwrap();
+        assert_eq!(archetypes.tables().len(), 2);
+        let (only_a, row) = archetypes.location(entities[0]).unwrap();
+        assert_eq!(archetypes.tables()[only_a].len(), 2);
+        assert_eq!(archetypes.tables()[only_a].entities()[row], entities[0]);
+        let (both, _) = archetypes.location(entities[1]).unwrap();
+        assert!(both != only_a);
+        assert_eq!(archetypes.location(entities[3]).unwrap().0, both);
+        assert_eq!(archetypes.get::<StubComponentA>(entities[2]).unwrap().counter, 2);
+        assert_eq!(archetypes.get::<StubComponentB>(entities[3]).unwrap().counter, 30);
+        assert!(archetypes.get::<StubComponentB>(entities[0]).is_none());
+    }
+
+    ecs.archetypes.as_mut().unwrap().for_each_pair(|b: &StubComponentB, a: &mut StubComponentA| a.counter += b.counter);
+    ecs.remove_component::<StubComponentB>(entities[1]).unwrap();
+    assert!(ecs.remove_component::<StubComponentB>(entities[1]).is_err());
+    ecs.deallocate_entity(entities[0]).unwrap();
+    ecs.add_component(entities[2], StubComponentA{counter: 20}).unwrap();
+    let mut counters = vec![];
+    ecs.archetypes.as_ref().unwrap().for_each(|entity, a: &StubComponentA| counters.push((entity, a.counter)));
+    counters.sort();
+    assert_eq!(counters, vec![(entities[1], 11), (entities[2], 20), (entities[3], 33)]);
+    assert!(ecs.archetypes.as_ref().unwrap().location(entities[0]).is_none());
+    assert_eq!(ecs.archetypes.as_ref().unwrap().tables().len(), 2);
+
+    let (networked, id) = ecs.spawn_networked();
+    assert_eq!(ecs.entity_for_network_id(id), Some(networked));
+    ecs.deallocate_entity(networked).unwrap();
+    assert_eq!(ecs.entity_for_network_id(id), None);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn archetype_unsupported_serialization_test(){
+    let mut registry = TypeRegistry::new();
+    registry.register_component::<StubComponentA>("stub_a").unwrap();
+    registry.register_hashed_component::<StubComponentA>("stub_a").unwrap();
+    let previous = registry.save(&ECS::new()).unwrap();
+    let mut ecs = ECS::with_layout(Layout::Archetype);
+    ecs.register_new_component::<StubComponentA>().unwrap();
+    let entity = ecs.allocate_new_entity();
+    ecs.add_component(entity, StubComponentA{counter: 1}).unwrap();
+    assert_eq!(ecs.save(&registry, &Ron).err(), Some(SerializeError::Unsupported("not supported by the archetype layout")));
+    assert!(ecs.checksum(&registry).is_err());
+    assert!(ecs.checksum_report(&registry).is_err());
+    assert!(ecs.delta_since(&registry, &previous).is_err());
+}
+
+#[test]
+fn archetype_unsupported_test(){
+    let mut ecs = ECS::with_layout(Layout::Archetype);
+    ecs.register_new_component::<StubComponentA>().unwrap();
+    let entity = ecs.allocate_new_entity();
+    ecs.add_component(entity, StubComponentA{counter: 1}).unwrap();
+    assert!(!ecs.archetypes.as_ref().unwrap().tables()[0].is_empty());
+
+    assert_eq!(ecs.clone_entity(entity), Err("not supported by the archetype layout"));
+    assert_eq!(ecs.snapshot().err(), Some("not supported by the archetype layout"));
+    let snapshot = ECS::new().snapshot().unwrap();
+    assert!(ecs.restore(&snapshot).is_err());
+    match ecs.try_get_component_read_handle::<StubComponentA>() {
+        Err(AccessError::Unsupported(_)) => {},
+        _ => panic!("archetype world handed out a component handle")
+    }
+    assert!(ecs.try_get_component_write_handle::<StubComponentA>().is_err());
+    assert_eq!(ecs.update_components::<StubComponentA>(), Err("not supported by the archetype layout"));
+    assert!(ecs.update_all().is_err());
+    assert!(ecs.update_all_parallel().is_err());
+    assert!(ecs.subscribe_component_events::<StubComponentA>().is_err());
+    assert!(ecs.get_mut::<StubComponentA>().is_err());
+    match ecs.fetch::<(ReadComp<StubComponentA>,)>() {
+        Err(AccessError::Unsupported(_)) => {},
+        _ => panic!("archetype world fetched a component handle")
+    }
+    assert!(ecs.fetch::<(WriteComp<StubComponentA>,)>().is_err());
+
+    let mut dense = ECS::new();
+    assert!(ECS::move_entity(&mut ecs, &mut dense, entity).is_err());
+    let other = dense.allocate_new_entity();
+    assert!(ECS::move_entity(&mut dense, &mut ecs, other).is_err());
+    assert_eq!(ecs.archetypes.as_ref().unwrap().get::<StubComponentA>(entity).unwrap().counter, 1);
+    assert_eq!(ecs.get_entity_iterator_live().into_iterator_wrapper().count(), 1);
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct InlinePosition {
+    x: f32,
+    y: f32
+}
+
+impl Component for InlinePosition {
+    type ComponentStorage = InlineComponentStorage<Self>;
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct InlineVelocity {
+    dx: f32,
+    dy: f32
+}
+
+impl Component for InlineVelocity {
+    type ComponentStorage = InlineComponentStorage<Self>;
+}
+
+#[test]
+fn stale_handle_write_test(){
+    let mut ecs = ECS::new();
+    let stale = ecs.allocate_new_entity();
+    ecs.register_new_component::<StubComponentA>().expect("unable to register new component");
+    ecs.add_component(stale, StubComponentA{ counter: 1 }).expect("not registered");
+    ecs.deallocate_entity(stale).unwrap();
+    let entity = ecs.allocate_new_entity();
+    assert_eq!(entity.index(), stale.index());
+    ecs.add_component(entity, StubComponentA{ counter: 2 }).expect("not registered");
+    let mut handle = ecs.get_component_write_handle::<StubComponentA>();
+    assert!(handle.get_mut(stale).is_none());
+    assert!(handle.get_component(stale).is_none());
+    assert!(!handle.insert(stale, StubComponentA{ counter: 3 }));
+    assert!(!handle.remove(stale));
+    assert_eq!(handle.get_component(entity).unwrap().counter, 2);
+    assert!(handle.insert(entity, StubComponentA{ counter: 4 }));
+    assert_eq!(handle.get_mut(entity).unwrap().counter, 4);
+    assert!(!handle.insert(Entity::DANGLING, StubComponentA{ counter: 5 }));
+    assert_eq!(handle.len(), 1);
+    drop(handle);
+
+    let reader = ecs.get_component_read_handle::<StubComponentA>();
+    assert!(reader.get_component(stale).is_none());
+    match reader.get(stale) {
+        ComponentEntry::Entry(_) => panic!("stale handle read another entity's component"),
+        ComponentEntry::Empty => {}
+    }
+    assert_eq!(reader.get_component(entity).unwrap().counter, 4);
+    drop(reader);
+
+    ecs.register_new_component::<InlinePosition>().unwrap();
+    ecs.add_component(entity, InlinePosition{x: 1.0, y: 0.0}).unwrap();
+    let mut positions = ecs.get_component_write_handle::<InlinePosition>();
+    assert!(positions.get_component(stale).is_none());
+    assert!(positions.get_mut(stale).is_none());
+    assert!(!positions.remove(stale));
+    assert!(!positions.insert(Entity::DANGLING, InlinePosition{x: 2.0, y: 0.0}));
+    assert_eq!(positions.get_component(entity).unwrap().x, 1.0);
+}
+
+#[test]
+fn modified_events_test(){
+    let mut ecs = ECS::new();
+    ecs.register_new_component::<StubComponentA>().unwrap();
+    ecs.register_new_component::<InlinePosition>().unwrap();
+    let entities = (0..3).map(|_| ecs.allocate_new_entity()).collect::<Vec<_>>();
+    for entity in entities.iter() {
+        ecs.add_component(*entity, StubComponentA{ counter: 0 }).unwrap();
+    }
+    ecs.add_component(entities[0], InlinePosition{x: 0.0, y: 0.0}).unwrap();
+    ecs.add_component(entities[2], InlinePosition{x: 0.0, y: 0.0}).unwrap();
+    let stubs = ecs.subscribe_component_events::<StubComponentA>().unwrap();
+    let positions = ecs.subscribe_component_events::<InlinePosition>().unwrap();
+    {
+        let mut handle = ecs.get_component_write_handle::<StubComponentA>();
+        for stub in handle.get_mut_iter().into_iterator_wrapper() {
+            stub.counter += 1;
+        }
+        handle.get_mut(entities[1]).unwrap().counter += 1;
+        //batched until the handle is dropped
+        assert_eq!(stubs.try_iter().count(), 0);
+    }
+    assert_eq!(stubs.try_iter().collect::<Vec<_>>(), entities.iter().map(|e| ComponentEvent::Modified(*e)).collect::<Vec<_>>());
+    {
+        let mut handle = ecs.get_component_write_handle::<InlinePosition>();
+        for (_, run) in handle.chunks_mut() {
+            for position in run.iter_mut() {
+                position.x += 1.0;
+            }
+        }
+    }
+    assert_eq!(positions.try_iter().collect::<Vec<_>>(), vec![ComponentEvent::Modified(entities[0]), ComponentEvent::Modified(entities[2])]);
+    //a read handle changes nothing
+    ecs.get_component_read_handle::<StubComponentA>();
+    assert_eq!(stubs.try_iter().count(), 0);
+}
+
+#[test]
+fn inline_chunks_test(){
+    let mut ecs = ECS::new();
+    ecs.register_new_component::<InlinePosition>().unwrap();
+    ecs.register_new_component::<InlineVelocity>().unwrap();
+    ecs.register_new_component::<StubComponentA>().unwrap();
+    let entities = (0..8).map(|_| ecs.allocate_new_entity()).collect::<Vec<_>>();
+    for entity in entities.iter() {
+        ecs.add_component(*entity, InlinePosition{x: entity.index() as f32, y: 0.0}).unwrap();
+    }
+    for i in [2, 3, 4, 6].iter() {
+        ecs.add_component(entities[*i], InlineVelocity{dx: 1.0, dy: 2.0}).unwrap();
+    }
+    ecs.add_component(entities[3], StubComponentA{counter: 1}).unwrap();
+    ecs.remove_component::<InlinePosition>(entities[5]).unwrap();
+    {
+        let positions = ecs.get_component_read_handle::<InlinePosition>();
+        let runs = positions.chunks().map(|(start, run)| (start, run.len())).collect::<Vec<_>>();
+        assert_eq!(runs, vec![(0, 5), (6, 2)]);
+        assert_eq!(positions.get_component(entities[5]), None);
+        assert_eq!(positions.get_component(entities[7]), Some(&InlinePosition{x: 7.0, y: 0.0}));
+        let velocities = ecs.get_component_read_handle::<InlineVelocity>();
+        assert_eq!(velocities.chunks().map(|(start, _)| start).collect::<Vec<_>>(), vec![2, 6]);
+        let stubs = ecs.get_component_read_handle::<StubComponentA>();
+        assert_eq!(stubs.get_iterator().join(positions.get_iterator()).into_iterator_wrapper().count(), 1);
+    }
+
+    let since = ecs.advance_tick();
+    {
+        let velocities = ecs.get_component_read_handle::<InlineVelocity>();
+        let mut positions = ecs.get_component_write_handle::<InlinePosition>();
+        for (start, run) in positions.chunks_mut() {
+            for (vstart, vrun) in velocities.chunks() {
+                for i in start.max(vstart)..(start + run.len()).min(vstart + vrun.len()) {
+                    run[i - start].x += vrun[i - vstart].dx;
+                    run[i - start].y += vrun[i - vstart].dy;
+                }
+            }
+        }
+    }
+    let positions = ecs.get_component_read_handle::<InlinePosition>();
+    let moved = positions.get_iterator().into_iterator_wrapper().map(|p| (p.x, p.y)).collect::<Vec<_>>();
+    assert_eq!(moved, vec![(0.0, 0.0), (1.0, 0.0), (3.0, 2.0), (4.0, 2.0), (5.0, 2.0), (7.0, 2.0), (7.0, 0.0)]);
+    assert_eq!(positions.get_changed_iterator(since - 1).into_iterator_wrapper().count(), 7);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn compaction_test(){
+    let mut allocator = EntityAllocator::new();
+    let _kept = allocator.allocate();
+    let dropped = allocator.allocate();
+    allocator.deallocate(dropped).unwrap();
+    assert_eq!(allocator.trim(), 1);
+    assert!(allocator.allocate() != dropped);
+
+    let mut registry = TypeRegistry::new();
+    registry.register_mapped_component::<Parent>("parent").unwrap();
+    let mut ecs = ECS::new();
+    ecs.register_new_component::<StubComponentA>().unwrap();
+    ecs.register_new_component::<InlinePosition>().unwrap();
+    ecs.register_new_component::<Parent>().unwrap();
+    let entities = (0..100).map(|_| ecs.allocate_new_entity()).collect::<Vec<_>>();
+    for entity in entities.iter() {
+        ecs.add_component(*entity, StubComponentA{counter: entity.index() as u8}).unwrap();
+        ecs.add_component(*entity, InlinePosition{x: entity.index() as f32, y: 0.0}).unwrap();
+    }
+    let (networked, id) = ecs.spawn_networked();
+    let kept = entities.iter().cloned().filter(|e| e.index() % 10 == 0 && e.index() < 50).collect::<Vec<_>>();
+    ecs.add_component(kept[1], Parent(kept[4])).unwrap();
+    ecs.add_component(kept[2], Parent(entities[99])).unwrap();
+    for entity in entities.iter().filter(|e| !kept.contains(e)) {
+        ecs.deallocate_entity(*entity).unwrap();
+    }
+    let before = ecs.heap_size();
+    ecs.maintain();
+    assert_eq!(ecs.entity_list.entity_list.len(), 101);
+    let trimmed = ecs.heap_size();
+    assert!(trimmed < before);
+
+    let events = ecs.subscribe_entity_events();
+    let map = ecs.compact(&registry);
+    assert_eq!(map.len(), 6);
+    let remapped = events.try_iter().collect::<Vec<_>>();
+    assert_eq!(remapped.len(), 6);
+    assert_eq!(remapped[0], EntityEvent::Remapped(kept[0], map.get(kept[0]).unwrap()));
+    assert_eq!(ecs.entity_list.entity_list.len(), 6);
+    assert!(ecs.heap_size() < trimmed);
+    assert_eq!(ecs.get_entity_iterator_live().into_iterator_wrapper().count(), 6);
+    for (i, old) in kept.iter().enumerate() {
+        let new = map.get(*old).unwrap();
+        assert_eq!(new.index(), i);
+        assert!(new.generation() > old.generation());
+        assert!(ecs.add_component(*old, StubComponentA{counter: 0}).is_err());
+        match ecs.get_component_read_handle::<StubComponentA>().get(new) {
+            ComponentEntry::Entry(a) => assert_eq!(a.counter as usize, old.index()),
+            ComponentEntry::Empty => panic!("component did not follow its entity")
+        }
+        assert_eq!(ecs.get_component_read_handle::<InlinePosition>().get_component(new).unwrap().x as usize, old.index());
+    }
+    let runs = ecs.get_component_read_handle::<InlinePosition>().chunks().map(|(start, run)| (start, run.len())).collect::<Vec<_>>();
+    assert_eq!(runs, vec![(0, 5)]);
+    assert_eq!(ecs.entity_for_network_id(id), map.get(networked));
+    assert_eq!(ecs.network_id(map.get(networked).unwrap()), Some(id));
+    let parents = ecs.get_component_read_handle::<Parent>();
+    assert_eq!(parents.get_component(map.get(kept[1]).unwrap()), Some(&Parent(map.get(kept[4]).unwrap())));
+    assert_eq!(parents.get_component(map.get(kept[2]).unwrap()), Some(&Parent(Entity::DANGLING)));
+}
+
+#[test]
+fn compaction_generation_limit_test(){
+    let mut allocator = EntityAllocator::new();
+    allocator.base_generation = Entity::MAX_GENERATION - 3;
+    let dropped = allocator.allocate();
+    let kept = allocator.allocate();
+    allocator.deallocate(dropped).unwrap();
+    let map = allocator.compact();
+    let moved = map.get(kept).unwrap();
+    assert_eq!(moved.index(), 0);
+    assert_eq!(moved.generation(), Entity::MAX_GENERATION - 2);
+    assert_eq!(allocator.base_generation, Entity::MAX_GENERATION - 1);
+
+    //no generation is left to renumber with, so nothing moves
+    let fresh = allocator.allocate();
+    allocator.deallocate(moved).unwrap();
+    let map = allocator.compact();
+    assert_eq!(map.get(fresh), Some(fresh));
+    assert_eq!(allocator.base_generation, Entity::MAX_GENERATION - 1);
+    assert!(allocator.is_live(fresh));
+    assert_eq!(allocator.allocate().generation(), Entity::MAX_GENERATION - 1);
+    assert!(allocator.validate().is_ok());
+    let mut retiring = EntityAllocator::new();
+    retiring.set_generation_limit(3).unwrap();
+    let mut freed = retiring.allocate();
+    let kept = retiring.allocate();
+    let mut stale = vec![];
+    while freed.index() == 0 {
+        stale.push(freed);
+        retiring.deallocate(freed).unwrap();
+        freed = retiring.allocate();
+    }
+    assert_eq!(retiring.retired(), 1);
+    let map = retiring.compact();
+    assert!(stale.iter().all(|old| !retiring.is_live(*old)));
+    assert!(retiring.is_live(map.get(kept).unwrap()));
+    assert!(retiring.is_live(map.get(freed).unwrap()));
+}
+
+#[test]
+fn reuse_policy_test(){
+    let mut lifo = EntityAllocator::new();
+    let a = lifo.allocate();
+    let b = lifo.allocate();
+    lifo.deallocate(a).unwrap();
+    lifo.deallocate(b).unwrap();
+    assert_eq!(lifo.allocate().index(), b.index());
+
+    let mut fifo = EntityAllocator::with_policy(ReusePolicy::Fifo);
+    let a = fifo.allocate();
+    let b = fifo.allocate();
+    fifo.deallocate(a).unwrap();
+    fifo.deallocate(b).unwrap();
+    let reused = fifo.allocate();
+    assert_eq!((reused.index(), reused.generation()), (a.index(), a.generation() + 1));
+    let reused = fifo.allocate();
+    assert_eq!((reused.index(), reused.generation()), (b.index(), b.generation() + 1));
+
+    let mut threshold = EntityAllocator::with_policy(ReusePolicy::Threshold(2));
+    let a = threshold.allocate();
+    threshold.deallocate(a).unwrap();
+    assert_eq!(threshold.allocate().index(), 1);
+    let b = threshold.allocate();
+    threshold.deallocate(b).unwrap();
+    assert_eq!(threshold.allocate().index(), a.index());
+    assert_eq!(threshold.allocate().index(), 3);
+
+    let mut retiring = EntityAllocator::new();
+    assert!(retiring.set_generation_limit(Entity::MAX_GENERATION + 1).is_err());
+    retiring.set_generation_limit(1).unwrap();
+    let a = retiring.allocate();
+    retiring.deallocate(a).unwrap();
+    let b = retiring.allocate();
+    assert_eq!((b.index(), b.generation()), (a.index(), 1));
+    retiring.deallocate(b).unwrap();
+    assert_eq!(retiring.retired(), 1);
+    assert_eq!(retiring.allocate().index(), 1);
+    assert_eq!(retiring.trim(), 0);
+    assert_eq!(retiring.entity_list.len(), 2);
+    assert!(!retiring.is_live(b));
+    assert!(retiring.allocate_at(Entity::new(b.index(), 2)).is_err());
+
+    //allocate_at never reissues a handle a slot has already held
+    let mut mirror = EntityAllocator::new();
+    let a = mirror.allocate();
+    mirror.deallocate(a).unwrap();
+    assert!(mirror.allocate_at(a).is_err());
+    let newer = Entity::new(a.index(), a.generation() + 1);
+    assert_eq!(mirror.allocate_at(newer), Ok(newer));
+    assert!(mirror.allocate_at(newer).is_err());
+    assert_eq!(mirror.allocate_at(Entity::new(3, 0)), Ok(Entity::new(3, 0)));
+    assert!(mirror.allocate_at(Entity::new(2, 0)).is_err());
+    assert!(mirror.allocate_at(Entity::new(2, 1)).is_ok());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn reuse_policy_save_test(){
+    let registry = TypeRegistry::new();
+    let mut ecs = ECS::with_policy(ReusePolicy::Fifo);
+    let a = ecs.allocate_new_entity();
+    let b = ecs.allocate_new_entity();
+    ecs.deallocate_entity(a).unwrap();
+    ecs.deallocate_entity(b).unwrap();
+    let bytes = ecs.save(&registry, &Ron).unwrap();
+    assert_eq!(ecs.allocate_new_entity().index(), a.index());
+    let mut loaded = ECS::load(&registry, &Ron, &bytes).unwrap();
+    assert_eq!(loaded.reuse_policy(), ReusePolicy::Fifo);
+    loaded.set_reuse_policy(ReusePolicy::Lifo);
+    assert_eq!(loaded.allocate_new_entity().index(), b.index());
+    let mut merged = ECS::new();
+    merged.merge(&registry, &Ron, &bytes).unwrap();
+    assert_eq!(merged.reuse_policy(), ReusePolicy::Lifo);
+}
+
+#[test]
+fn entity_handle_test(){
+    assert_eq!(::std::mem::size_of::<Entity>(), 8);
+    let mut allocator = EntityAllocator::new();
+    let first = allocator.allocate();
+    let second = allocator.allocate();
+    allocator.deallocate(first).unwrap();
+    let reused = allocator.allocate();
+    assert_eq!(reused.to_string(), "0v1");
+    assert_eq!(format!("{:?}", second), "Entity(1v0)");
+    assert!(first < reused && reused < second);
+    assert_eq!(reused.to_bits(), 1);
+    assert_eq!(second.to_bits(), 1 << GENERATION_BITS);
+    assert_eq!(Entity::from_bits(second.to_bits()), second);
+    assert_eq!(allocator.entity(0), Some(reused));
+    assert_eq!(allocator.live().collect::<Vec<_>>(), vec![reused, second]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn entity_serialization_test(){
+    let mut allocator = EntityAllocator::new();
+    let first = allocator.allocate();
+    allocator.deallocate(first).unwrap();
+    let reused = allocator.allocate();
+    let saved = ::serde_json::to_string(&reused).unwrap();
+    assert_eq!(saved, "[0,1]");
+    assert_eq!(::serde_json::from_str::<Entity>(&saved).unwrap(), reused);
+    let too_old = format!("[0,{}]", Entity::MAX_GENERATION as u128 + 1);
+    assert!(::serde_json::from_str::<Entity>(&too_old).is_err());
+}
@@ -8,7 +8,9 @@ use ecs::component::Component;
 use ecs::component::DenseComponentStorage;
 use ecs::component::ComponentIterator;
 use ecs::component::ComponentIteratorMut;
+use ecs::component::Tracked;
 use ecs::component::Iter;
+use ecs::archetype::Layout;
 use std::fs::File;
 use std::io::Write;
 use std::hash::BuildHasherDefault;
@@ -33,10 +35,6 @@ struct R{
 
 impl Component for R{
     type ComponentStorage = DenseComponentStorage<Self>;
-
-    fn update(&mut self) {
-        unimplemented!()
-    }
 }
 
 #[derive(Clone)]
@@ -46,10 +44,6 @@ struct W1{
 
 impl Component for W1{
     type ComponentStorage = DenseComponentStorage<Self>;
-
-    fn update(&mut self) {
-        unimplemented!()
-    }
 }
 
 #[derive(Clone)]
@@ -86,7 +80,18 @@ fn setup() -> ECS {
     ecs
 }
 
-fn system_w1(read_r: ComponentIterator<R>, write_w1: ComponentIteratorMut<W1>) {
+fn setup_archetype() -> ECS {
+    let mut ecs = ECS::with_layout(Layout::Archetype);
+    for _ in 0..STANDARD {
+        let ent = ecs.allocate_new_entity();
+        ecs.add_component(ent, R { x: 32.0 }).expect("not registered");
+        ecs.add_component(ent, W1 { x: 0.0 }).expect("not registered");
+        ecs.add_component(ent, W2 { x: 0.0 }).expect("not registered");
+    }
+    ecs
+}
+
+fn system_w1(read_r: ComponentIterator<R>, write_w1: Tracked<ComponentIteratorMut<W1>>) {
     let joint = read_r.join(write_w1);
     let iterator = joint.into_iterator_wrapper();
     for (r, w1) in iterator {
@@ -94,7 +99,7 @@ fn system_w1(read_r: ComponentIterator<R>, write_w1: ComponentIteratorMut<W1>) {
     }
 }
 
-fn system_w2(read_r: ComponentIterator<R>, write_w2: ComponentIteratorMut<W2>){
+fn system_w2(read_r: ComponentIterator<R>, write_w2: Tracked<ComponentIteratorMut<W2>>){
     let joint = read_r.join(write_w2);
     let iterator = joint.into_iterator_wrapper();
     for (r, w2) in iterator {
@@ -212,5 +217,72 @@ fn main() {
             .set_y_range(Fix(0.0), Auto);
         fg.show();
     }
+
+    {
+        //setup 10000 with components in archetype tables
+        let counters = &[papi::Counter::PAPI_L1_DCM, papi::Counter::PAPI_L2_DCM];
+        let mut counters = unsafe {
+            papi::CounterSet::new(counters)
+        };
+        let start = counters.read();
+        setup_archetype();
+        let stop = counters.accum();
+
+        println!("allocated 10000 entities with components in archetype tables with {} L1 misses, {} L2 misses",
+                 stop[0] - start[0], stop[1] - start[1]);
+
+        let result_arr = stop.iter().zip(start.iter()).map(|(x, y)| (x - y) as f32).collect::<Vec<f32>>();
+        let mut fg = Figure::new();
+        fg.set_terminal("pngcairo", "./data/setup_10000_entities_with_components_archetype.png");
+
+        fg.axes2d()
+            .boxes(&[1., 2.], &result_arr, &[Color("gray"), BorderColor("black")])
+            .set_title("setup 10000 entities with components in archetype tables", &[])
+            .set_x_ticks_custom(
+                vec![
+                    Major(1. as f32, Fix("L1 Data Cache Miss".into())),
+                    Major(2. as f32, Fix("L2 Data Cache Miss ".into())),
+                ],
+                &[],
+                &[],
+            )
+            .set_y_range(Fix(0.0), Auto);
+        fg.show();
+    }
+
+    {
+        //update archetype tables
+        let counters = &[papi::Counter::PAPI_L1_DCM, papi::Counter::PAPI_L2_DCM];
+        let mut counters = unsafe {
+            papi::CounterSet::new(counters)
+        };
+        let mut ecs = setup_archetype();
+        let archetypes = ecs.archetypes.as_mut().expect("archetype layout");
+
+        let start = counters.read();
+        archetypes.for_each_pair(|r: &R, w1: &mut W1| w1.x = r.x);
+        archetypes.for_each_pair(|r: &R, w2: &mut W2| w2.x = r.x);
+        let stop = counters.accum();
+
+        println!("updated archetype components sequentially with {} L1 misses, {} L2 misses",
+                 stop[0] - start[0], stop[1] - start[1]);
+
+        let result_arr = stop.iter().zip(start.iter()).map(|(x, y)| (x - y) as f32).collect::<Vec<f32>>();
+        let mut fg = Figure::new();
+        fg.set_terminal("pngcairo", "./data/update_10000_entities_with_components_archetype.png");
+        fg.axes2d()
+            .boxes(&[1., 2.], &result_arr, &[Color("gray"), BorderColor("black")])
+            .set_title("update 10000 entities with components in archetype tables", &[])
+            .set_x_ticks_custom(
+                vec![
+                    Major(1. as f32, Fix("L1 Data Cache Miss".into())),
+                    Major(2. as f32, Fix("L2 Data Cache Miss ".into())),
+                ],
+                &[],
+                &[],
+            )
+            .set_y_range(Fix(0.0), Auto);
+        fg.show();
+    }
 }
 